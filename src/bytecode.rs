@@ -0,0 +1,640 @@
+use crate::pixel_machine::{Address, Color, Data, Filter, Op, Program, Sources, Span};
+use std::convert::TryInto;
+use std::fmt;
+use std::path::Path;
+
+/// Set in the flags when the paths and spans of the source follow the ops.
+const DEBUG_INFO: u8 = 1;
+
+/// The version of the format, bumped whenever the encoding of programs changes.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// The bytes every compiled program starts with.
+pub const MAGIC: &[u8; 4] = b"DASC";
+
+/// Errors that may occur when reading a compiled program.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The file ends early or holds something a compiled program can't.
+    Corrupt {
+        /// The byte the problem was found at.
+        offset: usize,
+        reason: String,
+    },
+    /// The file does not start with the magic bytes.
+    NotBytecode,
+    /// The file was compiled with another version of the format.
+    UnsupportedVersion { found: u16 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Corrupt { offset, reason } => {
+                write!(f, "corrupt compiled program at byte {}: {}", offset, reason)
+            }
+            Error::NotBytecode => write!(f, "not a compiled program"),
+            Error::UnsupportedVersion { found } => write!(
+                f,
+                "compiled with format version {} but version {} is supported, recompile the program",
+                found, FORMAT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Reads a compiled program, adding the file at the given path and the files its spans point into to `sources`.
+/// Programs compiled without debug info have no spans, so errors only point at the compiled file.
+pub fn decode(bytes: &[u8], path: &Path, sources: &mut Sources) -> Result<Program, Error> {
+    if !is_bytecode(bytes) {
+        return Err(Error::NotBytecode);
+    }
+
+    let mut reader = Reader {
+        bytes,
+        offset: MAGIC.len(),
+    };
+    let found = reader.u16()?;
+    if found != FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion { found });
+    }
+
+    let flags = reader.u8()?;
+    let entry = reader.u32()? as usize;
+
+    let count = reader.u32()?;
+    let constants = (0..count)
+        .map(|_| reader.data())
+        .collect::<Result<Vec<Data>, Error>>()?;
+
+    let count = reader.u32()? as usize;
+    let mut ops = vec![];
+    for _ in 0..count {
+        ops.push(reader.op(&constants, count)?);
+    }
+    if entry > ops.len() {
+        return Err(reader.corrupt(format!("entry {} is past the last op", entry)));
+    }
+
+    sources.add(path, String::new());
+    let mut spans = vec![];
+    if flags & DEBUG_INFO != 0 {
+        let count = reader.u32()?;
+        let files = (0..count)
+            .map(|_| Ok(sources.add(Path::new(&reader.string()?), String::new())))
+            .collect::<Result<Vec<usize>, Error>>()?;
+
+        for _ in 0..ops.len() {
+            let column = reader.u32()? as usize;
+            let file = reader.u32()? as usize;
+            let len = reader.u32()? as usize;
+            let line = reader.u32()? as usize;
+            let file = *files
+                .get(file)
+                .ok_or_else(|| reader.corrupt(format!("span points at undefined file {}", file)))?;
+
+            spans.push(Span {
+                column,
+                file,
+                len,
+                line,
+            });
+        }
+    }
+
+    if reader.offset != bytes.len() {
+        return Err(reader.corrupt("unexpected bytes after the program".into()));
+    }
+
+    Program::new(entry, ops, spans).map_err(|e| reader.corrupt(e.to_string()))
+}
+
+/// Writes a compiled program, storing each distinct literal once in a constant pool.
+/// The paths of `sources` and the spans of the ops are written as debug info when given.
+pub fn encode(program: &Program, sources: Option<&Sources>) -> Vec<u8> {
+    let mut constants = vec![];
+    let mut ops = Writer::default();
+    for op in program.ops() {
+        ops.op(&mut constants, op);
+    }
+
+    let spans = program.spans();
+    let debug_info = sources.filter(|_| !spans.is_empty() && spans.len() == program.ops().len());
+
+    let mut writer = Writer::default();
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.u16(FORMAT_VERSION);
+    writer.u8(if debug_info.is_some() { DEBUG_INFO } else { 0 });
+    writer.u32(program.entry() as u32);
+
+    writer.u32(constants.len() as u32);
+    for data in &constants {
+        writer.data(data);
+    }
+
+    writer.u32(program.ops().len() as u32);
+    writer.bytes.extend(ops.bytes);
+
+    if let Some(sources) = debug_info {
+        let files = spans.iter().map(|span| span.file + 1).max().unwrap_or(0);
+        writer.u32(files as u32);
+        for file in 0..files {
+            writer.string(&sources.path(file).to_string_lossy());
+        }
+
+        for span in spans {
+            writer.u32(span.column as u32);
+            writer.u32(span.file as u32);
+            writer.u32(span.len as u32);
+            writer.u32(span.line as u32);
+        }
+    }
+
+    writer.bytes
+}
+
+/// Returns whether the bytes start like a compiled program rather than source code.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Reads the values of a compiled program, tracking the offset for errors.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Creates an error at the current offset.
+    fn corrupt(&self, reason: String) -> Error {
+        Error::Corrupt {
+            offset: self.offset,
+            reason,
+        }
+    }
+
+    /// Reads a literal of the constant pool.
+    fn data(&mut self) -> Result<Data, Error> {
+        let data = match self.u8()? {
+            0 => Data::Bool(self.u8()? != 0),
+            1 => {
+                let [r, g, b, a]: [u8; 4] = self.take(4)?.try_into().unwrap_or_default();
+                Data::Color(Color { r, g, b, a })
+            }
+            2 => Data::F32(self.f32()?),
+            3 => Data::String(self.string()?),
+            4 => Data::U32(self.u32()?),
+            5 => Data::U8(self.u8()?),
+            6 => Data::Vec2([self.f32()?, self.f32()?]),
+            7 => Data::Vec3([self.f32()?, self.f32()?, self.f32()?]),
+            8 => Data::Vec4([self.f32()?, self.f32()?, self.f32()?, self.f32()?]),
+            tag => return Err(self.corrupt(format!("unknown constant tag {}", tag))),
+        };
+
+        Ok(data)
+    }
+
+    fn f32(&mut self) -> Result<f32, Error> {
+        Ok(f32::from_le_bytes(
+            self.take(4)?.try_into().unwrap_or_default(),
+        ))
+    }
+
+    /// Reads an op, looking up its operands in the constant pool.
+    fn op(&mut self, constants: &[Data], ops: usize) -> Result<Op, Error> {
+        let constant = |reader: &mut Self| {
+            let idx = reader.u32()? as usize;
+            constants
+                .get(idx)
+                .cloned()
+                .ok_or_else(|| reader.corrupt(format!("undefined constant {}", idx)))
+        };
+        let name = |reader: &mut Self| match constant(reader)? {
+            Data::String(name) => Ok(name),
+            data => Err(reader.corrupt(format!("expected a name, got {:?}", data))),
+        };
+
+        let op = match self.u8()? {
+            0 => Op::Add,
+            1 => Op::And,
+            2 => match self.u32()? as usize {
+                address if address < ops => Op::Call(address),
+                address => {
+                    return Err(self.corrupt(format!("call to {} is past the last op", address)))
+                }
+            },
+            3 => Op::Data(constant(self)?),
+            4 => Op::Dimensions,
+            5 => Op::Divide,
+            6 => Op::Do,
+            7 => Op::Dot,
+            8 => Op::Drop,
+            9 => Op::Dup,
+            10 => Op::Else,
+            11 => Op::End,
+            12 => Op::Equal,
+            13 => Op::FragPos,
+            14 => Op::Frame,
+            15 => Op::GreaterThan,
+            16 => Op::GreaterThanEqual,
+            17 => Op::If,
+            18 => Op::Index,
+            19 => Op::Length,
+            20 => Op::LessThan,
+            21 => Op::LessThanEqual,
+            22 => Op::MakeColor,
+            23 => Op::Modulo,
+            24 => Op::Multiply,
+            25 => Op::Normalize,
+            26 => Op::Return,
+            27 => Op::Rot,
+            28 => Op::RotN,
+            29 => {
+                let address = match self.u8()? {
+                    0 => None,
+                    1 => Some(Address::Border),
+                    2 => Some(Address::Clamp),
+                    3 => Some(Address::Mirror),
+                    4 => Some(Address::Repeat),
+                    tag => return Err(self.corrupt(format!("unknown address tag {}", tag))),
+                };
+                let filter = match self.u8()? {
+                    0 => Filter::Bicubic,
+                    1 => Filter::Bilinear,
+                    2 => Filter::Nearest,
+                    tag => return Err(self.corrupt(format!("unknown filter tag {}", tag))),
+                };
+
+                Op::Sample { address, filter }
+            }
+            30 => Op::SplitColor,
+            31 => Op::SplitVec,
+            32 => Op::Subtract,
+            33 => {
+                // Swizzles pick between 1 and 4 of the components `x`, `y`, `z` and `w`.
+                let len = self.u8()?;
+                if !(1..=4).contains(&len) {
+                    return Err(self.corrupt(format!("swizzle of {} components", len)));
+                }
+
+                let components = self.take(len as usize)?;
+                if let Some(component) = components.iter().find(|c| **c >= 4) {
+                    return Err(self.corrupt(format!("swizzle of component {}", component)));
+                }
+
+                Op::Swizzle(components.iter().map(|c| *c as usize).collect())
+            }
+            34 => Op::Texture(name(self)?),
+            35 => Op::TextureIndex,
+            36 => Op::TexturePixel,
+            37 => Op::Time,
+            38 => Op::Times,
+            39 => Op::ToColor,
+            40 => Op::ToF32,
+            41 => Op::ToU32,
+            42 => Op::ToU8,
+            43 => Op::ToVec4,
+            44 => Op::Uniform(name(self)?),
+            45 => Op::UniformValue,
+            46 => Op::Vec2,
+            47 => Op::Vec3,
+            48 => Op::Vec4,
+            49 => Op::While,
+            tag => return Err(self.corrupt(format!("unknown op tag {}", tag))),
+        };
+
+        Ok(op)
+    }
+
+    /// Reads a UTF-8 string prefixed by its length.
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.corrupt("invalid UTF-8 string".into()))
+    }
+
+    /// Reads the given number of bytes.
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset.saturating_add(len))
+            .ok_or_else(|| self.corrupt("unexpected end of file".into()))?;
+        self.offset += len;
+
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(
+            self.take(2)?.try_into().unwrap_or_default(),
+        ))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(
+            self.take(4)?.try_into().unwrap_or_default(),
+        ))
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+}
+
+/// Writes the values of a compiled program in little endian.
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    /// Writes the index of the literal in the constant pool, adding it if it is new.
+    fn constant(&mut self, constants: &mut Vec<Data>, data: Data) {
+        let idx = match constants.iter().position(|constant| *constant == data) {
+            Some(idx) => idx,
+            None => {
+                constants.push(data);
+                constants.len() - 1
+            }
+        };
+
+        self.u32(idx as u32);
+    }
+
+    /// Writes a literal of the constant pool.
+    fn data(&mut self, data: &Data) {
+        match data {
+            Data::Bool(b) => {
+                self.u8(0);
+                self.u8(*b as u8);
+            }
+            Data::Color(color) => {
+                self.u8(1);
+                self.bytes
+                    .extend_from_slice(&[color.r, color.g, color.b, color.a]);
+            }
+            Data::F32(f) => {
+                self.u8(2);
+                self.f32(*f);
+            }
+            Data::String(s) => {
+                self.u8(3);
+                self.string(s);
+            }
+            Data::U32(u) => {
+                self.u8(4);
+                self.u32(*u);
+            }
+            Data::U8(u) => {
+                self.u8(5);
+                self.u8(*u);
+            }
+            Data::Vec2(v) => {
+                self.u8(6);
+                v.iter().for_each(|c| self.f32(*c));
+            }
+            Data::Vec3(v) => {
+                self.u8(7);
+                v.iter().for_each(|c| self.f32(*c));
+            }
+            Data::Vec4(v) => {
+                self.u8(8);
+                v.iter().for_each(|c| self.f32(*c));
+            }
+        }
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes an op, adding its literals and names to the constant pool.
+    fn op(&mut self, constants: &mut Vec<Data>, op: &Op) {
+        let tag = match op {
+            Op::Add => 0,
+            Op::And => 1,
+            Op::Call(_) => 2,
+            Op::Data(_) => 3,
+            Op::Dimensions => 4,
+            Op::Divide => 5,
+            Op::Do => 6,
+            Op::Dot => 7,
+            Op::Drop => 8,
+            Op::Dup => 9,
+            Op::Else => 10,
+            Op::End => 11,
+            Op::Equal => 12,
+            Op::FragPos => 13,
+            Op::Frame => 14,
+            Op::GreaterThan => 15,
+            Op::GreaterThanEqual => 16,
+            Op::If => 17,
+            Op::Index => 18,
+            Op::Length => 19,
+            Op::LessThan => 20,
+            Op::LessThanEqual => 21,
+            Op::MakeColor => 22,
+            Op::Modulo => 23,
+            Op::Multiply => 24,
+            Op::Normalize => 25,
+            Op::Return => 26,
+            Op::Rot => 27,
+            Op::RotN => 28,
+            Op::Sample { .. } => 29,
+            Op::SplitColor => 30,
+            Op::SplitVec => 31,
+            Op::Subtract => 32,
+            Op::Swizzle(_) => 33,
+            Op::Texture(_) => 34,
+            Op::TextureIndex => 35,
+            Op::TexturePixel => 36,
+            Op::Time => 37,
+            Op::Times => 38,
+            Op::ToColor => 39,
+            Op::ToF32 => 40,
+            Op::ToU32 => 41,
+            Op::ToU8 => 42,
+            Op::ToVec4 => 43,
+            Op::Uniform(_) => 44,
+            Op::UniformValue => 45,
+            Op::Vec2 => 46,
+            Op::Vec3 => 47,
+            Op::Vec4 => 48,
+            Op::While => 49,
+        };
+        self.u8(tag);
+
+        match op {
+            Op::Call(address) => self.u32(*address as u32),
+            Op::Data(data) => self.constant(constants, data.clone()),
+            Op::Sample { address, filter } => {
+                self.u8(match address {
+                    None => 0,
+                    Some(Address::Border) => 1,
+                    Some(Address::Clamp) => 2,
+                    Some(Address::Mirror) => 3,
+                    Some(Address::Repeat) => 4,
+                });
+                self.u8(match filter {
+                    Filter::Bicubic => 0,
+                    Filter::Bilinear => 1,
+                    Filter::Nearest => 2,
+                });
+            }
+            Op::Swizzle(components) => {
+                self.u8(components.len() as u8);
+                for component in components {
+                    self.u8(*component as u8);
+                }
+            }
+            Op::Texture(name) | Op::Uniform(name) => {
+                self.constant(constants, Data::String(name.clone()))
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes a UTF-8 string prefixed by its length.
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel_machine::PixelMachine;
+
+    const SOURCE: &str = "
+        : gray dup dup 255 makeColor ;
+        if $bright do 1.5 else fragPos vec2 0.5 0.5 vec2 * .x end
+        255.0 * toU8 gray
+        @mummy sampleNearest.clamp \"name\" uniform drop drop
+    ";
+
+    mod decode {
+        use super::*;
+
+        #[test]
+        fn corrupt() {
+            let mut bytes = encode(&Program::compile("1 2 +").unwrap(), None);
+            bytes.truncate(bytes.len() - 1);
+            match decode(&bytes, Path::new("test.dasc"), &mut Sources::default()) {
+                Err(Error::Corrupt { reason, .. }) => assert_eq!("unexpected end of file", reason),
+                result => panic!("expected a corrupt program, got {:?}", result),
+            }
+
+            assert_eq!(
+                Err(Error::NotBytecode),
+                decode(b"1 2 +", Path::new("test.das"), &mut Sources::default())
+            );
+        }
+
+        #[test]
+        fn invalid_swizzle() {
+            let bytes = encode(&Program::compile("1.0 .x").unwrap(), None);
+            let swizzle = bytes.len() - 2;
+            for (idx, value) in [(swizzle, 0), (swizzle, 5), (swizzle + 1, 4)] {
+                let mut corrupt = bytes.clone();
+                corrupt[idx] = value;
+
+                match decode(&corrupt, Path::new("test.dasc"), &mut Sources::default()) {
+                    Err(Error::Corrupt { reason, .. }) => {
+                        assert!(reason.starts_with("swizzle of"), "{}", reason)
+                    }
+                    result => panic!("expected a corrupt program, got {:?}", result),
+                }
+            }
+        }
+
+        #[test]
+        fn renders_runtime_error() {
+            let mut sources = Sources::default();
+            let program =
+                Program::compile_file(Path::new("test.das"), "255\n0 1 /", &mut sources).unwrap();
+            let bytes = encode(&program.optimize(), Some(&sources));
+
+            let mut sources = Sources::default();
+            let decoded = decode(&bytes, Path::new("test.dasc"), &mut sources).unwrap();
+            let e = PixelMachine::new(0, 0, 1, 1, vec![])
+                .run(&decoded)
+                .unwrap_err();
+
+            let rendered = sources.render(&e);
+            assert!(rendered.contains("--> test.das:2:5"), "{}", rendered);
+            assert!(!rendered.contains("0 1 /"), "{}", rendered);
+        }
+
+        #[test]
+        fn version_mismatch() {
+            let mut bytes = encode(&Program::compile("1 2 +").unwrap(), None);
+            bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&2u16.to_le_bytes());
+
+            let e = decode(&bytes, Path::new("test.dasc"), &mut Sources::default()).unwrap_err();
+            assert_eq!(Error::UnsupportedVersion { found: 2 }, e);
+            assert_eq!(
+                "compiled with format version 2 but version 1 is supported, recompile the program",
+                e.to_string()
+            );
+        }
+    }
+
+    mod encode {
+        use super::*;
+
+        #[test]
+        fn pools_constants() {
+            let once = encode(&Program::compile("255 toU32").unwrap(), None);
+            let twice = encode(&Program::compile("255 255 +").unwrap(), None);
+
+            // The second literal only adds its op and index.
+            assert_eq!(once.len() + 5, twice.len());
+        }
+
+        #[test]
+        fn round_trips() {
+            let mut sources = Sources::default();
+            let program =
+                Program::compile_file(Path::new("test.das"), SOURCE, &mut sources).unwrap();
+
+            let bytes = encode(&program, Some(&sources));
+            let mut decoded_sources = Sources::default();
+            let decoded = decode(&bytes, Path::new("test.dasc"), &mut decoded_sources).unwrap();
+            assert_eq!(program.entry(), decoded.entry());
+            assert_eq!(program.ops(), decoded.ops());
+            assert_eq!(program.block(4), decoded.block(4));
+            let location = |span: &Span| (span.column, span.len, span.line);
+            assert_eq!(
+                program.spans().iter().map(location).collect::<Vec<_>>(),
+                decoded.spans().iter().map(location).collect::<Vec<_>>()
+            );
+            assert!(decoded.spans().iter().all(|span| span.file == 1));
+            assert_eq!(Path::new("test.das"), decoded_sources.path(1));
+        }
+
+        #[test]
+        fn strips_spans() {
+            let program = Program::compile(SOURCE).unwrap();
+            let stripped = encode(&program, None);
+            assert!(stripped.len() < encode(&program, Some(&Sources::default())).len());
+
+            let decoded = decode(&stripped, Path::new("test.dasc"), &mut Sources::default());
+            assert_eq!(Vec::<Span>::new(), decoded.unwrap().spans());
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use crate::output::Output;
+use crate::pixel_machine::{Address, Color, Data, Fit, Resample, Texture};
+use crate::RenderError;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// An input texture, either the output of an earlier pass, a path or a path with how to load and address it.
+#[derive(Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Input {
+    Pass {
+        #[serde(default)]
+        address: Address,
+        /// The color outside of the texture when using the `border` address.
+        #[serde(default)]
+        border: [u8; 4],
+        /// The name the program looks the texture up by, defaulting to the name of the pass.
+        #[serde(default)]
+        name: Option<String>,
+        /// The name of the earlier pass.
+        pass: String,
+        /// Which of the pass' render targets to read.
+        #[serde(default)]
+        target: usize,
+    },
+    Path(String),
+    Texture {
+        #[serde(default)]
+        address: Address,
+        /// The color outside of the texture when using the `border` address.
+        #[serde(default)]
+        border: [u8; 4],
+        /// How the image is resized to the pass.
+        #[serde(default)]
+        fit: Fit,
+        /// The name the program looks the texture up by, defaulting to the file stem.
+        #[serde(default)]
+        name: Option<String>,
+        path: String,
+        /// The filter used when resizing the image.
+        #[serde(default)]
+        resample: Resample,
+    },
+}
+
+impl Input {
+    /// Returns the input with the given name.
+    pub fn named(&self, name: &str) -> Self {
+        let mut input = match self {
+            Input::Path(path) => Input::Texture {
+                address: Address::default(),
+                border: [0; 4],
+                fit: Fit::default(),
+                name: None,
+                path: path.clone(),
+                resample: Resample::default(),
+            },
+            input => input.clone(),
+        };
+
+        if let Input::Pass { name: n, .. } | Input::Texture { name: n, .. } = &mut input {
+            *n = Some(name.into());
+        }
+
+        input
+    }
+
+    /// Returns the name the program looks the input up by.
+    pub fn name(&self) -> String {
+        match self {
+            Input::Pass {
+                name: Some(name), ..
+            }
+            | Input::Texture {
+                name: Some(name), ..
+            } => name.clone(),
+            Input::Pass { pass, .. } => pass.clone(),
+            Input::Path(path) | Input::Texture { path, .. } => Path::new(path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Creates the texture for the given image, named and addressed as configured.
+    pub fn texture(&self, image: image::DynamicImage) -> Texture {
+        let mut texture = Texture::new(image);
+        texture.name = Some(self.name());
+        if let Input::Pass {
+            address, border, ..
+        }
+        | Input::Texture {
+            address, border, ..
+        } = self
+        {
+            let [r, g, b, a] = *border;
+            texture.address = *address;
+            texture.border = Color { r, g, b, a };
+        }
+
+        texture
+    }
+}
+
+/// The inputs of a pass, either a list or a map from the names the program uses to inputs.
+/// The entries of a map are kept in the order they are written, duplicates included, so they can be rejected.
+#[derive(Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Inputs {
+    List(Vec<Input>),
+    Named(#[serde(deserialize_with = "named_inputs")] Vec<(String, Input)>),
+}
+
+impl Default for Inputs {
+    fn default() -> Self {
+        Inputs::List(vec![])
+    }
+}
+
+impl Inputs {
+    /// Returns the first name given to more than one input of a map.
+    pub fn duplicate(&self) -> Option<&str> {
+        match self {
+            Inputs::List(_) => None,
+            Inputs::Named(inputs) => inputs.iter().enumerate().find_map(|(idx, (name, _))| {
+                inputs[..idx]
+                    .iter()
+                    .any(|(earlier, _)| earlier == name)
+                    .then_some(name.as_str())
+            }),
+        }
+    }
+
+    /// Returns the inputs in the order of their indices, naming the inputs of a map by their key.
+    /// The inputs of a map are indexed in the order they are written in the config.
+    pub fn to_vec(&self) -> Vec<Input> {
+        match self {
+            Inputs::List(inputs) => inputs.clone(),
+            Inputs::Named(inputs) => inputs
+                .iter()
+                .map(|(name, input)| input.named(name))
+                .collect(),
+        }
+    }
+}
+
+/// A value the program reads by name, either a bool, a color or a number.
+#[derive(Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Uniform {
+    Bool(bool),
+    Color([u8; 4]),
+    Number(serde_json::Number),
+}
+
+impl Uniform {
+    /// Returns the value as data, treating numbers the same as literals in a program.
+    pub fn data(&self) -> Data {
+        match self {
+            Uniform::Bool(b) => Data::Bool(*b),
+            Uniform::Color([r, g, b, a]) => Data::Color((*r, *g, *b, *a).into()),
+            Uniform::Number(n) => Data::parse(&n.to_string())
+                .unwrap_or_else(|| Data::F32(n.as_f64().unwrap_or_default() as f32)),
+        }
+    }
+}
+
+/// Deserializes a map of inputs into its entries, in the order they are written.
+fn named_inputs<'de, D>(deserializer: D) -> Result<Vec<(String, Input)>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct Visitor;
+
+    impl<'de> serde::de::Visitor<'de> for Visitor {
+        type Value = Vec<(String, Input)>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a map from names to inputs")
+        }
+
+        fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+        where
+            A: serde::de::MapAccess<'de>,
+        {
+            let mut inputs = vec![];
+            while let Some(entry) = map.next_entry()? {
+                inputs.push(entry);
+            }
+
+            Ok(inputs)
+        }
+    }
+
+    deserializer.deserialize_map(Visitor)
+}
+
+/// What to render, loaded from a JSON file.
+#[derive(serde::Deserialize)]
+pub struct Cfg {
+    /// The default width of every pass.
+    pub width: u32,
+    /// The default height of every pass.
+    pub height: u32,
+    /// The frames per second of an animation, defaulting to 30.
+    #[serde(default)]
+    pub fps: Option<f32>,
+    /// The number of frames to render, defaulting to 1.
+    /// Animations are written as a GIF for `.gif` outputs and as a numbered sequence otherwise.
+    #[serde(default)]
+    pub frames: Option<u32>,
+    #[serde(default)]
+    pub inputs: Inputs,
+    /// A single output file, kept for older configs.
+    #[serde(default)]
+    pub output: Option<Output>,
+    /// The output files, filled from the top colors of the stack in push order.
+    #[serde(default)]
+    pub outputs: Vec<Output>,
+    /// The passes to render in order. When empty, the config itself is the only pass.
+    #[serde(default)]
+    pub passes: Vec<Pass>,
+    /// The file the config was loaded from, which its paths are relative to.
+    #[serde(skip)]
+    pub path: PathBuf,
+    pub program: Option<String>,
+    /// The values read by `$name` in the programs, which `--set name=value` overrides.
+    #[serde(default)]
+    pub uniforms: HashMap<String, Uniform>,
+}
+
+impl Cfg {
+    /// Returns the directory the paths in the config are relative to.
+    pub fn directory(&self) -> &Path {
+        self.path.parent().unwrap_or_else(|| Path::new(""))
+    }
+
+    /// Returns the frames per second of an animation.
+    pub fn fps(&self) -> f32 {
+        self.fps.unwrap_or(30.0)
+    }
+
+    /// Returns the number of frames to render.
+    pub fn frames(&self) -> u32 {
+        self.frames.unwrap_or(1)
+    }
+
+    /// Loads the config from a JSON file.
+    pub fn load(path: &Path) -> Result<Self, RenderError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| RenderError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let mut cfg: Cfg = serde_json::from_str(&contents).map_err(|e| RenderError::Config {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })?;
+        cfg.path = path.to_path_buf();
+
+        Ok(cfg)
+    }
+
+    /// Returns the passes to render, treating a config without passes as a single pass.
+    /// Returns `None` when the config has neither passes nor a program.
+    pub fn passes(&self) -> Option<Vec<Pass>> {
+        if !self.passes.is_empty() {
+            return Some(self.passes.clone());
+        }
+
+        Some(vec![Pass {
+            height: None,
+            inputs: self.inputs.clone(),
+            name: "main".into(),
+            outputs: self
+                .output
+                .iter()
+                .chain(self.outputs.iter())
+                .cloned()
+                .collect(),
+            program: self.program.clone()?,
+            targets: None,
+            width: None,
+        }])
+    }
+
+    /// Returns the values of the uniforms the programs read by name.
+    pub fn uniforms(&self) -> HashMap<String, Data> {
+        self.uniforms
+            .iter()
+            .map(|(name, uniform)| (name.clone(), uniform.data()))
+            .collect()
+    }
+}
+
+/// A single program run over every pixel, whose render targets are kept for later passes.
+#[derive(Clone, serde::Deserialize)]
+pub struct Pass {
+    /// Overrides the height of the config.
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub inputs: Inputs,
+    pub name: String,
+    /// The files to write the render targets to. Passes without outputs are only kept in memory.
+    #[serde(default)]
+    pub outputs: Vec<Output>,
+    pub program: String,
+    /// The number of render targets, defaulting to the number of outputs.
+    #[serde(default)]
+    pub targets: Option<usize>,
+    /// Overrides the width of the config.
+    #[serde(default)]
+    pub width: Option<u32>,
+}
+
+impl Pass {
+    /// Returns the number of colors the program leaves on the stack.
+    pub fn targets(&self) -> usize {
+        self.targets.unwrap_or_else(|| self.outputs.len().max(1))
+    }
+}
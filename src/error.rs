@@ -0,0 +1,213 @@
+use crate::{bytecode, output, pixel_machine};
+use image::ImageError;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors that may occur when loading and rendering a config.
+#[derive(Debug)]
+pub enum RenderError {
+    /// A compiled program could not be read.
+    Bytecode {
+        path: PathBuf,
+        source: bytecode::Error,
+    },
+    /// The config could not be parsed or is inconsistent.
+    Config {
+        path: PathBuf,
+        /// What is wrong with the config.
+        reason: String,
+    },
+    /// An input image could not be decoded.
+    Decode { path: PathBuf, source: ImageError },
+    /// An output could not be written.
+    Encode(output::Error),
+    /// A file could not be read or written.
+    Io { path: PathBuf, source: io::Error },
+    /// A program failed to compile, check or run.
+    Program {
+        source: Box<pixel_machine::Error>,
+        /// The files the program was compiled from, used to render the error.
+        sources: pixel_machine::Sources,
+    },
+    /// The command line arguments are invalid.
+    Usage {
+        /// What is wrong with the arguments.
+        reason: String,
+    },
+}
+
+impl RenderError {
+    /// Returns the exit code of the category of the error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RenderError::Usage { .. } => 2,
+            RenderError::Config { .. } => 3,
+            RenderError::Io { .. } => 4,
+            RenderError::Decode { .. } => 5,
+            RenderError::Encode(_) => 6,
+            RenderError::Program { .. } => 7,
+            RenderError::Bytecode { .. } => 8,
+        }
+    }
+
+    /// Renders the error for the terminal, pointing at the source of program errors.
+    pub fn render(&self) -> String {
+        match self {
+            RenderError::Program { source, sources } => sources.render(source),
+            e => format!("error: {}\n", e),
+        }
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Bytecode { path, source } => write!(f, "{}: {}", path.display(), source),
+            RenderError::Config { path, reason } => write!(f, "{}: {}", path.display(), reason),
+            RenderError::Decode { path, source } => {
+                write!(f, "failed to decode {}: {}", path.display(), source)
+            }
+            RenderError::Encode(e) => write!(f, "{}", e),
+            RenderError::Io { path, source } => {
+                write!(f, "failed to access {}: {}", path.display(), source)
+            }
+            RenderError::Program { source, sources } => {
+                let file = source.context().span.map(|span| span.file).unwrap_or(0);
+                write!(f, "{}: {}", sources.path(file).display(), source)
+            }
+            RenderError::Usage { reason } => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RenderError::Bytecode { source, .. } => Some(source),
+            RenderError::Decode { source, .. } => Some(source),
+            RenderError::Encode(e) => Some(e),
+            RenderError::Io { source, .. } => Some(source),
+            RenderError::Program { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<output::Error> for RenderError {
+    fn from(e: output::Error) -> Self {
+        RenderError::Encode(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn errors() -> Vec<RenderError> {
+        vec![
+            RenderError::Bytecode {
+                path: "shader.dasc".into(),
+                source: bytecode::Error::UnsupportedVersion { found: 0 },
+            },
+            RenderError::Config {
+                path: "cfg.json".into(),
+                reason: "required a program or passes".into(),
+            },
+            RenderError::Decode {
+                path: "mummy.jpg".into(),
+                source: ImageError::Unsupported(image::error::UnsupportedError::from(
+                    image::error::ImageFormatHint::Unknown,
+                )),
+            },
+            RenderError::Encode(output::Error::Encode {
+                path: "out.png".into(),
+                source: ImageError::IoError(io::Error::from(io::ErrorKind::PermissionDenied)),
+            }),
+            RenderError::Io {
+                path: "missing.das".into(),
+                source: io::Error::from(io::ErrorKind::NotFound),
+            },
+            {
+                let mut sources = pixel_machine::Sources::default();
+                let source = pixel_machine::Program::compile_file(
+                    Path::new("test.das"),
+                    "add",
+                    &mut sources,
+                )
+                .unwrap_err();
+
+                RenderError::Program {
+                    source: Box::new(source),
+                    sources,
+                }
+            },
+            RenderError::Usage {
+                reason: "required config JSON file".into(),
+            },
+        ]
+    }
+
+    mod exit_code {
+        use super::*;
+
+        #[test]
+        fn distinct_per_category() {
+            let mut codes: Vec<i32> = errors().iter().map(RenderError::exit_code).collect();
+            assert!(codes.iter().all(|code| *code > 1));
+
+            codes.sort_unstable();
+            codes.dedup();
+            assert_eq!(errors().len(), codes.len());
+        }
+    }
+
+    mod render {
+        use super::*;
+
+        #[test]
+        fn includes_path() {
+            let paths = [
+                "shader.dasc",
+                "cfg.json",
+                "mummy.jpg",
+                "out.png",
+                "missing.das",
+                "test.das",
+            ];
+            for (e, path) in errors().iter().zip(paths) {
+                let rendered = e.render();
+                assert!(rendered.starts_with("error: "), "{}", rendered);
+                assert!(rendered.contains(path), "{}", rendered);
+            }
+        }
+
+        #[test]
+        fn program_points_at_file() {
+            let rendered = errors()[5].render();
+            assert!(rendered.contains("--> test.das"), "{}", rendered);
+        }
+    }
+
+    mod source {
+        use super::*;
+        use std::error::Error;
+
+        #[test]
+        fn chains_causes() {
+            let errors = errors();
+            let io = errors
+                .iter()
+                .find(|e| matches!(e, RenderError::Io { .. }))
+                .unwrap();
+            assert_eq!(
+                io::Error::from(io::ErrorKind::NotFound).to_string(),
+                io.source().unwrap().to_string()
+            );
+
+            let usage = errors.last().unwrap();
+            assert!(usage.source().is_none());
+        }
+    }
+}
@@ -0,0 +1,56 @@
+pub mod bytecode;
+mod cfg;
+mod error;
+pub mod output;
+mod pixel_machine;
+mod renderer;
+mod repl;
+
+pub use cfg::*;
+pub use error::RenderError;
+pub use pixel_machine::*;
+pub use renderer::Renderer;
+
+use std::path::Path;
+
+/// Compiles the program at the given path, optimized, into bytecode written to `output`.
+/// The paths and spans of the source are kept when `debug_info` is set, so errors point at the source.
+pub fn compile(debug_info: bool, output: &Path, path: &Path) -> Result<(), RenderError> {
+    let source = std::fs::read_to_string(path).map_err(|source| RenderError::Io {
+        path: path.into(),
+        source,
+    })?;
+
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let file = path.strip_prefix(directory).unwrap_or(path);
+    let mut sources = Sources::new(directory);
+    let program = match Program::compile_file(file, &source, &mut sources) {
+        Ok(program) => program.optimize(),
+        Err(e) => {
+            return Err(RenderError::Program {
+                source: Box::new(e),
+                sources,
+            })
+        }
+    };
+
+    let bytes = bytecode::encode(&program, Some(&sources).filter(|_| debug_info));
+    std::fs::write(output, bytes).map_err(|source| RenderError::Io {
+        path: output.into(),
+        source,
+    })
+}
+
+/// Renders the first frame of the config with its own uniforms, returning the first render target of the last pass.
+pub fn render(cfg: &Cfg) -> Result<image::RgbaImage, RenderError> {
+    let renderer = Renderer::new(cfg, &cfg.uniforms())?;
+    let mut passes = renderer.render_frame(0)?;
+
+    passes
+        .pop()
+        .and_then(|targets| targets.into_iter().next())
+        .ok_or_else(|| RenderError::Config {
+            path: cfg.path.clone(),
+            reason: "the last pass renders no targets".into(),
+        })
+}
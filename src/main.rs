@@ -1,145 +1,229 @@
-mod pixel_machine;
-
-use image::imageops::FilterType;
-use pixel_machine::*;
-use rayon::prelude::*;
-use std::sync::Arc;
-
-type Texture = Arc<image::DynamicImage>;
-
-#[derive(serde::Deserialize)]
-pub struct Cfg {
-    pub width: u32,
-    pub height: u32,
-    pub inputs: Vec<String>,
-    pub output: String,
-    pub program: String,
+use das_ubershader::{Cfg, Data, RenderError, Renderer};
+use std::io;
+
+fn main() {
+    if let Err(e) = run() {
+        eprint!("{}", e.render());
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Parses a uniform given on the command line, such as `true`, `0.5`, `4` or `255,0,0,255`.
+fn parse_uniform(value: &str) -> Option<Data> {
+    if let Some(data) = Data::parse(value) {
+        return Some(data);
+    }
+
+    let channels = value
+        .split(',')
+        .map(|channel| channel.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<u8>>>()?;
+    match channels[..] {
+        [r, g, b, a] => Some(Data::Color((r, g, b, a).into())),
+        _ => None,
+    }
 }
 
-fn main() -> Result<(), Error> {
+/// Loads the config given on the command line, then renders and saves every frame.
+/// The `compile` subcommand compiles a program to bytecode instead.
+fn run() -> Result<(), RenderError> {
     let start = std::time::Instant::now();
 
+    let args = Args::parse(std::env::args().skip(1))?;
+    let working_dir = std::env::current_dir().map_err(|source| RenderError::Io {
+        path: ".".into(),
+        source,
+    })?;
+
+    // Compile program
+    if args.compile {
+        let path = working_dir.join(&args.file_path);
+        let output = match &args.output {
+            Some(output) => working_dir.join(output),
+            None => path.with_extension("dasc"),
+        };
+        println!("Compiling {} to {}", args.file_path, output.display());
+        return das_ubershader::compile(args.debug, &output, &path);
+    }
+
     // Load config
-    let (directory, cfg) = {
-        let mut file_path = None;
-        for (idx, arg) in std::env::args().enumerate() {
-            if idx == 1 {
-                file_path = Some(arg);
+    println!("Loading cfg from {}", args.file_path);
+    let cfg = Cfg::load(&working_dir.join(&args.file_path))?;
+
+    // Set uniforms, letting the command line override the config
+    let mut uniforms = cfg.uniforms();
+    for set in &args.overrides {
+        let uniform = set
+            .split_once('=')
+            .and_then(|(name, value)| Some((name, parse_uniform(value)?)));
+        match uniform {
+            Some((name, data)) => {
+                uniforms.insert(name.into(), data);
             }
-        }
-
-        match file_path {
-            Some(file_path) => {
-                println!("Loading cfg from {}", file_path);
-                let path = std::path::Path::new(&file_path);
-                let working_dir = std::env::current_dir().unwrap().to_path_buf();
-                let mut p = working_dir.clone();
-                p.push(path);
-                let path = p;
-                let contents = std::fs::read_to_string(&path).unwrap();
-                let cfg: Cfg = serde_json::from_str(&contents).unwrap();
-
-                let directory = {
-                    let mut directory = working_dir.clone();
-                    match &path.parent() {
-                        Some(parent) => {
-                            directory.push(parent);
-                        }
-                        None => {}
-                    }
-                    directory
-                };
-
-                (directory, cfg)
+            None => {
+                return Err(RenderError::Usage {
+                    reason: format!("invalid uniform {}, expected name=value", set),
+                })
             }
-            None => panic!("Required config JSON file!"),
         }
-    };
-
-    // Set working values
-    let width = cfg.width;
-    let height = cfg.height;
-
-    let output_file = {
-        let mut file = directory.clone();
-        file.push(cfg.output);
-        file.to_str().unwrap().to_string()
-    };
-    let program = {
-        let mut file = directory.clone();
-        file.push(cfg.program);
-
-        std::fs::read_to_string(file).unwrap()
-    };
-
-    // Load all textures
-    let textures = {
-        let mut textures = vec![];
-        for tex in cfg.inputs.iter() {
-            let mut file = directory.clone();
-            file.push(tex);
-            textures.push(file.to_str().unwrap().to_string());
-        }
-        textures
-    };
-
-    let textures: Vec<Texture> = {
-        let mut t = vec![];
-        textures
-            .par_iter()
-            .map(|texture| {
-                let loaded_img = image::open(texture).unwrap().resize_to_fill(
-                    width,
-                    height,
-                    FilterType::Nearest,
-                );
-                Arc::new(loaded_img)
-            })
-            .collect_into_vec(&mut t);
-        t
-    };
-
-    // Build up pixels
-    let pixels = {
-        let mut pixels = vec![];
-        for x in 0..width {
-            for y in 0..height {
-                pixels.push((x, y));
-            }
+    }
+
+    let renderer = Renderer::new(&cfg, &uniforms)?;
+    if args.dump_ops {
+        print!("{}", renderer.dump_ops());
+    }
+    if args.repl {
+        return renderer.repl(io::stdin().lock(), &mut io::stdout());
+    }
+    if let Some(position) = args.trace {
+        for line in renderer.trace(position)? {
+            println!("{}", line);
         }
-        pixels
-    };
+        return Ok(());
+    }
 
-    // Process pixels
-    let new_pixels: Vec<(u32, u32, Result<Color, Error>)> = pixels
-        .par_iter()
-        .map(|(x, y)| {
-            let color =
-                PixelMachine::new(*x, *y, width, height, textures.clone()).interpret(&program);
+    renderer.save()?;
 
-            (*x, *y, color)
-        })
-        .collect();
-
-    // Write calculated pixels to image
-    let mut new_image: image::ImageBuffer<image::Rgba<u8>, Vec<u8>> =
-        image::ImageBuffer::new(width, height);
-
-    for (x, y, color) in new_pixels {
-        let color = match color {
-            Ok(c) => c,
-            Err(e) => {
-                println!("ERROR: {:?}", e);
-                return Err(e);
+    println!("DURATION: {:?}", std::time::Instant::now() - start);
+    Ok(())
+}
+
+/// The arguments given on the command line.
+struct Args {
+    /// Whether to compile a program instead of rendering, given by the `compile` subcommand.
+    compile: bool,
+    /// Whether to keep the paths and spans errors point at in the compiled program, given by `--debug`.
+    debug: bool,
+    /// Whether to print the ops of every pass before and after optimizing, given by `--dump-ops`.
+    dump_ops: bool,
+    /// The config to render, or the program to compile.
+    file_path: String,
+    /// Where to write the compiled program, given by `--output path`.
+    output: Option<String>,
+    /// The uniforms set with `--set name=value`.
+    overrides: Vec<String>,
+    /// Whether to start the REPL instead of rendering, given by the `repl` subcommand.
+    repl: bool,
+    /// The pixel to trace instead of rendering, given by `--trace x,y`.
+    trace: Option<(u32, u32)>,
+}
+
+impl Args {
+    /// Parses the arguments following the name of the executable.
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, RenderError> {
+        let mut args = args.peekable();
+        let compile = args.next_if_eq("compile").is_some();
+        let repl = !compile && args.next_if_eq("repl").is_some();
+
+        let mut debug = false;
+        let mut dump_ops = false;
+        let mut file_path = None;
+        let mut output = None;
+        let mut overrides = vec![];
+        let mut trace = None;
+        while let Some(arg) = args.next() {
+            if arg == "--debug" {
+                debug = true;
+            } else if arg == "--dump-ops" {
+                dump_ops = true;
+            } else if arg == "--output" {
+                match args.next() {
+                    Some(path) => output = Some(path),
+                    None => {
+                        return Err(RenderError::Usage {
+                            reason: "required a path after --output".into(),
+                        })
+                    }
+                }
+            } else if arg == "--set" {
+                match args.next() {
+                    Some(set) => overrides.push(set),
+                    None => {
+                        return Err(RenderError::Usage {
+                            reason: "required name=value after --set".into(),
+                        })
+                    }
+                }
+            } else if arg == "--trace" {
+                let position = args
+                    .next()
+                    .and_then(|position| {
+                        let (x, y) = position.split_once(',')?;
+                        Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+                    })
+                    .ok_or_else(|| RenderError::Usage {
+                        reason: "required x,y after --trace".into(),
+                    })?;
+                trace = Some(position);
+            } else if arg.starts_with('-') {
+                return Err(RenderError::Usage {
+                    reason: format!("unknown option {}", arg),
+                });
+            } else if file_path.is_none() {
+                file_path = Some(arg);
+            } else {
+                return Err(RenderError::Usage {
+                    reason: format!("unexpected argument {}", arg),
+                });
             }
-        };
+        }
 
-        *new_image.get_pixel_mut(x, y) = image::Rgba::<u8>([color.r, color.g, color.b, color.a]);
+        let file_path = file_path.ok_or_else(|| RenderError::Usage {
+            reason: if compile {
+                "required program file".into()
+            } else {
+                "required config JSON file".into()
+            },
+        })?;
+
+        Ok(Self {
+            compile,
+            debug,
+            dump_ops,
+            file_path,
+            output,
+            overrides,
+            repl,
+            trace,
+        })
     }
+}
 
-    // Save and return
-    new_image.save(output_file).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    println!("DURATION: {:?}", std::time::Instant::now() - start);
-    Ok(())
+    mod parse {
+        use super::*;
+
+        fn parse(args: &[&str]) -> Result<Args, RenderError> {
+            Args::parse(args.iter().map(|arg| arg.to_string()))
+        }
+
+        #[test]
+        fn options() {
+            let args =
+                parse(&["compile", "shader.das", "--debug", "--output", "out.dasc"]).unwrap();
+            assert_eq!(true, args.compile);
+            assert_eq!(true, args.debug);
+            assert_eq!("shader.das", args.file_path);
+            assert_eq!(Some("out.dasc".into()), args.output);
+        }
+
+        #[test]
+        fn unexpected_argument() {
+            assert!(matches!(
+                parse(&["cfg.json", "other.json"]),
+                Err(RenderError::Usage { reason }) if reason == "unexpected argument other.json"
+            ));
+        }
+
+        #[test]
+        fn unknown_option() {
+            assert!(matches!(
+                parse(&["cfg.json", "--dum-ops"]),
+                Err(RenderError::Usage { reason }) if reason == "unknown option --dum-ops"
+            ));
+        }
+    }
 }
@@ -0,0 +1,513 @@
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::hdr::HdrEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::openexr::OpenExrEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::tiff::TiffEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{
+    ColorType, Delay, DynamicImage, ImageBuffer, ImageEncoder, ImageError, ImageResult, Rgba,
+    RgbaImage,
+};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How much effort is spent compressing a PNG.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Compression {
+    Best,
+    Default,
+    #[default]
+    Fast,
+}
+
+impl From<Compression> for CompressionType {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Best => CompressionType::Best,
+            Compression::Default => CompressionType::Default,
+            Compression::Fast => CompressionType::Fast,
+        }
+    }
+}
+
+/// Errors that may occur when writing an output.
+#[derive(Debug)]
+pub enum Error {
+    /// The encoder failed to write the file.
+    Encode { path: PathBuf, source: ImageError },
+    /// A setting of the format is out of range.
+    InvalidSetting {
+        format: &'static str,
+        /// What the setting must be.
+        reason: String,
+    },
+    /// The format can't represent the rendered image.
+    Unrepresentable {
+        format: &'static str,
+        path: PathBuf,
+        /// What the format can't store.
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Encode { path, source } => {
+                write!(f, "failed to write {}: {}", path.display(), source)
+            }
+            Error::InvalidSetting { format, reason } => write!(f, "{} {}", format, reason),
+            Error::Unrepresentable {
+                format,
+                path,
+                reason,
+            } => write!(
+                f,
+                "{} can't store {} in {}, choose another format",
+                format,
+                reason,
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Encode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The format an output is encoded in, along with its settings.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase", tag = "format")]
+pub enum Format {
+    /// OpenEXR, storing floats with alpha.
+    Exr,
+    /// A GIF, animated when rendering several frames.
+    Gif,
+    /// Radiance HDR, storing floats without alpha.
+    Hdr,
+    /// A JPEG without alpha.
+    Jpeg {
+        /// The quality from 1 to 100, defaulting to 90.
+        #[serde(default)]
+        quality: Option<u8>,
+    },
+    Png {
+        /// Either 8 or 16 bits per channel, defaulting to 8.
+        /// At 16 bits the 8 rendered bits are widened, so 255 is stored as 65535.
+        #[serde(default, rename = "bitDepth")]
+        bit_depth: Option<u8>,
+        #[serde(default)]
+        compression: Compression,
+    },
+    Tiff,
+    /// A lossless WebP.
+    Webp,
+}
+
+impl Format {
+    /// Encodes a single image to the given file.
+    pub fn encode(&self, image: &RgbaImage, path: &Path) -> Result<(), Error> {
+        self.validate()?;
+
+        let encode_error = |source| Error::Encode {
+            path: path.to_path_buf(),
+            source,
+        };
+        let mut writer = File::create(path)
+            .map(BufWriter::new)
+            .map_err(|e| encode_error(ImageError::IoError(e)))?;
+        let (width, height) = image.dimensions();
+
+        let opaque = image.pixels().all(|pixel| pixel[3] == u8::MAX);
+        let unrepresentable = |reason| Error::Unrepresentable {
+            format: self.name(),
+            path: path.to_path_buf(),
+            reason,
+        };
+
+        match self {
+            Format::Exr => {
+                let floats = DynamicImage::ImageRgba8(image.clone()).into_rgba32f();
+                let bytes: Vec<u8> = floats.iter().flat_map(|c| c.to_ne_bytes()).collect();
+                OpenExrEncoder::new(writer)
+                    .write_image(&bytes, width, height, ColorType::Rgba32F)
+                    .map_err(encode_error)
+            }
+            Format::Gif => GifEncoder::new(writer)
+                .encode(image, width, height, ColorType::Rgba8)
+                .map_err(encode_error),
+            Format::Hdr => {
+                if !opaque {
+                    return Err(unrepresentable("transparency"));
+                }
+
+                let pixels: Vec<image::Rgb<f32>> = image
+                    .pixels()
+                    .map(|pixel| image::Rgb([0, 1, 2].map(|c| pixel[c] as f32 / 255.0)))
+                    .collect();
+                HdrEncoder::new(writer)
+                    .encode(&pixels, width as usize, height as usize)
+                    .map_err(encode_error)
+            }
+            Format::Jpeg { .. } => {
+                if !opaque {
+                    return Err(unrepresentable("transparency"));
+                }
+
+                let rgb = DynamicImage::ImageRgba8(image.clone()).into_rgb8();
+                JpegEncoder::new_with_quality(&mut writer, self.quality())
+                    .encode(rgb.as_raw(), width, height, ColorType::Rgb8)
+                    .map_err(encode_error)
+            }
+            Format::Png { compression, .. } => {
+                let encoder =
+                    PngEncoder::new_with_quality(writer, (*compression).into(), FilterType::Sub);
+                if self.bit_depth() == 16 {
+                    write_png_16(
+                        encoder,
+                        &DynamicImage::ImageRgba8(image.clone()).into_rgba16(),
+                    )
+                } else {
+                    encoder.write_image(image.as_raw(), width, height, ColorType::Rgba8)
+                }
+                .map_err(encode_error)
+            }
+            Format::Tiff => TiffEncoder::new(writer)
+                .encode(image.as_raw(), width, height, ColorType::Rgba8)
+                .map_err(encode_error),
+            Format::Webp => WebPEncoder::new_lossless(writer)
+                .write_image(image.as_raw(), width, height, ColorType::Rgba8)
+                .map_err(encode_error),
+        }
+    }
+
+    /// Returns the name of the format used in errors.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Exr => "exr",
+            Format::Gif => "gif",
+            Format::Hdr => "hdr",
+            Format::Jpeg { .. } => "jpeg",
+            Format::Png { .. } => "png",
+            Format::Tiff => "tiff",
+            Format::Webp => "webp",
+        }
+    }
+
+    /// Checks that the format can be written and its settings are in range.
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            Format::Jpeg {
+                quality: Some(quality),
+            } if !(1..=100).contains(quality) => Err(Error::InvalidSetting {
+                format: self.name(),
+                reason: format!("quality must be between 1 and 100, got {}", quality),
+            }),
+            Format::Png {
+                bit_depth: Some(bit_depth),
+                ..
+            } if *bit_depth != 8 && *bit_depth != 16 => Err(Error::InvalidSetting {
+                format: self.name(),
+                reason: format!("bit depth must be 8 or 16, got {}", bit_depth),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the bits per channel of a PNG.
+    fn bit_depth(&self) -> u8 {
+        match self {
+            Format::Png { bit_depth, .. } => bit_depth.unwrap_or(8),
+            _ => 8,
+        }
+    }
+
+    /// Returns the quality of a JPEG.
+    fn quality(&self) -> u8 {
+        match self {
+            Format::Jpeg { quality } => quality.unwrap_or(90),
+            _ => 90,
+        }
+    }
+}
+
+/// An output file, either a path whose extension picks the format or a path with an explicit format.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Output {
+    Path(String),
+    Encoded {
+        #[serde(flatten)]
+        format: Format,
+        path: String,
+    },
+}
+
+impl Output {
+    /// Returns the explicit format of the output.
+    pub fn format(&self) -> Option<&Format> {
+        match self {
+            Output::Path(_) => None,
+            Output::Encoded { format, .. } => Some(format),
+        }
+    }
+
+    /// Returns whether the output is a GIF, either by its format or by the extension of its path.
+    pub fn is_gif(&self) -> bool {
+        match self.format() {
+            Some(format) => *format == Format::Gif,
+            None => Path::new(self.path())
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("gif")),
+        }
+    }
+
+    /// Returns the path of the output.
+    pub fn path(&self) -> &str {
+        match self {
+            Output::Path(path) | Output::Encoded { path, .. } => path,
+        }
+    }
+}
+
+/// Saves a single frame of an output.
+/// The frames of an animation are numbered, so frame 1 of `out.png` is written to `out_0001.png`.
+pub fn save(
+    frame: Option<u32>,
+    image: &RgbaImage,
+    output: &Output,
+    path: &Path,
+) -> Result<(), Error> {
+    let path = match frame {
+        Some(frame) => {
+            let stem = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default();
+            let extension = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .unwrap_or_default();
+            path.with_file_name(format!("{}_{:04}.{}", stem, frame, extension))
+        }
+        None => path.to_path_buf(),
+    };
+
+    match output.format() {
+        Some(format) => format.encode(image, &path),
+        None => image
+            .save(&path)
+            .map_err(|source| Error::Encode { path, source }),
+    }
+}
+
+/// Saves the frames of an animated GIF, shown at the given frames per second.
+/// The `fps` must be a positive number.
+pub fn save_gif(fps: f32, images: Vec<RgbaImage>, path: &Path) -> Result<(), Error> {
+    if !fps.is_finite() || fps <= 0.0 {
+        return Err(Error::InvalidSetting {
+            format: "gif",
+            reason: format!("fps must be a positive number, got {}", fps),
+        });
+    }
+
+    let encode_error = |source| Error::Encode {
+        path: path.to_path_buf(),
+        source,
+    };
+    let file = File::create(path).map_err(|e| encode_error(ImageError::IoError(e)))?;
+
+    let delay = Delay::from_saturating_duration(Duration::from_secs_f32(1.0 / fps));
+    let mut encoder = GifEncoder::new(file);
+    encoder.set_repeat(Repeat::Infinite).map_err(encode_error)?;
+    encoder
+        .encode_frames(
+            images
+                .into_iter()
+                .map(|image| image::Frame::from_parts(image, 0, 0, delay)),
+        )
+        .map_err(encode_error)
+}
+
+/// Writes a PNG with 16 bits per channel, which the encoder stores in big endian.
+fn write_png_16<W: Write>(
+    encoder: PngEncoder<W>,
+    image: &ImageBuffer<Rgba<u16>, Vec<u16>>,
+) -> ImageResult<()> {
+    let bytes: Vec<u8> = image.iter().flat_map(|c| c.to_ne_bytes()).collect();
+    encoder.write_image(&bytes, image.width(), image.height(), ColorType::Rgba16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(alpha: u8) -> RgbaImage {
+        RgbaImage::from_pixel(2, 2, image::Rgba([10, 20, 30, alpha]))
+    }
+
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("das_ubershader_{}", name))
+    }
+
+    mod encode {
+        use super::*;
+
+        #[test]
+        fn exr() {
+            let path = temp("float.exr");
+            Format::Exr.encode(&image(128), &path).unwrap();
+
+            let decoded = image::open(&path).unwrap();
+            assert_eq!(ColorType::Rgba32F, decoded.color());
+            assert_eq!(image(128), decoded.into_rgba8());
+        }
+
+        #[test]
+        fn jpeg_with_alpha() {
+            let path = temp("alpha.jpg");
+            let format = Format::Jpeg { quality: None };
+            match format.encode(&image(128), &path) {
+                Err(Error::Unrepresentable { reason, .. }) => assert_eq!("transparency", reason),
+                result => panic!("expected an unrepresentable error, got {:?}", result),
+            }
+        }
+
+        #[test]
+        fn png_16_bit() {
+            let path = temp("wide.png");
+            let format = Format::Png {
+                bit_depth: Some(16),
+                compression: Compression::Best,
+            };
+            format.encode(&image(255), &path).unwrap();
+
+            let decoded = image::open(&path).unwrap().into_rgba16();
+            assert_eq!(&Rgba([2570, 5140, 7710, 65535]), decoded.get_pixel(0, 0));
+        }
+
+        #[test]
+        fn webp_lossless() {
+            let path = temp("lossless.webp");
+            Format::Webp.encode(&image(128), &path).unwrap();
+
+            assert_eq!(image(128), image::open(&path).unwrap().into_rgba8());
+        }
+    }
+
+    mod output {
+        use super::*;
+
+        #[test]
+        fn deserializes_format() {
+            let output: Output =
+                serde_json::from_str(r#"{ "path": "out.jpg", "format": "jpeg", "quality": 80 }"#)
+                    .unwrap();
+            assert_eq!(
+                Output::Encoded {
+                    format: Format::Jpeg { quality: Some(80) },
+                    path: "out.jpg".into()
+                },
+                output
+            );
+
+            let output: Output = serde_json::from_str(r#""out.png""#).unwrap();
+            assert_eq!(Output::Path("out.png".into()), output);
+        }
+
+        #[test]
+        fn rejects_unknown_format() {
+            let output =
+                serde_json::from_str::<Output>(r#"{ "path": "out.bmp", "format": "bmp" }"#);
+            assert!(output.is_err());
+        }
+    }
+
+    mod save {
+        use super::*;
+
+        #[test]
+        fn numbers_frames() {
+            let output = Output::Path("frames.png".into());
+            save(Some(3), &image(255), &output, &temp("frames.png")).unwrap();
+
+            let saved = image::open(temp("frames_0003.png")).unwrap();
+            assert_eq!(image(255), saved.into_rgba8());
+        }
+    }
+
+    mod save_gif {
+        use super::*;
+
+        #[test]
+        fn invalid_fps() {
+            for fps in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+                let path = temp("animation.gif");
+                match save_gif(fps, vec![image(255), image(255)], &path) {
+                    Err(Error::InvalidSetting { reason, .. }) => {
+                        assert!(reason.starts_with("fps must be a positive number"))
+                    }
+                    result => panic!("expected an invalid setting, got {:?}", result),
+                }
+            }
+        }
+    }
+
+    mod validate {
+        use super::*;
+
+        #[test]
+        fn jpeg_quality() {
+            let format = Format::Jpeg { quality: Some(0) };
+            assert!(format.validate().is_err());
+            assert!(Format::Jpeg { quality: Some(100) }.validate().is_ok());
+        }
+
+        #[test]
+        fn png_bit_depth() {
+            let format = Format::Png {
+                bit_depth: Some(12),
+                compression: Compression::Fast,
+            };
+            assert_eq!(
+                "png bit depth must be 8 or 16, got 12",
+                format.validate().unwrap_err().to_string()
+            );
+        }
+    }
+
+    mod write_png_16 {
+        use super::*;
+
+        #[test]
+        fn big_endian() {
+            let path = temp("big_endian.png");
+            let image = ImageBuffer::from_pixel(1, 1, Rgba([0x0102, 0x0304, 0x0506, 0xfffe]));
+            let writer = BufWriter::new(File::create(&path).unwrap());
+            write_png_16(PngEncoder::new(writer), &image).unwrap();
+
+            // A swapped byte order would read 0x0201 instead of 0x0102.
+            let decoded = image::open(&path).unwrap().into_rgba16();
+            assert_eq!(
+                [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0xff, 0xfe],
+                decoded
+                    .get_pixel(0, 0)
+                    .0
+                    .map(u16::to_be_bytes)
+                    .concat()
+                    .as_slice()
+            );
+        }
+    }
+}
@@ -0,0 +1,941 @@
+use super::{BlockKind, Context, Data, Error, Op, Span, Type};
+use std::collections::HashMap;
+
+/// Statically checks the given ops by simulating the types on the stack.
+/// Ensures every op gets the inputs it requires and that a color is left for each output.
+pub fn check(entry: usize, ops: &[Op], outputs: usize, spans: &[Span]) -> Result<(), Error> {
+    let mut checker = Checker {
+        calls: vec![],
+        effects: HashMap::new(),
+        ops,
+        spans,
+    };
+    let mut stack = TypeStack::default();
+    checker.simulate(&mut stack, entry)?;
+
+    let context = Context {
+        expected: vec![Type::Color; outputs],
+        op: ops[entry..]
+            .last()
+            .map(|op| op.name())
+            .unwrap_or_default()
+            .into(),
+        span: spans.get(entry..).and_then(|spans| spans.last()).copied(),
+    };
+
+    for _ in 0..outputs {
+        match stack.slots.pop() {
+            Some(slot) => {
+                if !accepts(&Type::Color, &slot.ty) {
+                    return Err(Error::InvalidResult {
+                        context,
+                        got: slot.ty,
+                    });
+                }
+            }
+            None => {
+                if stack.open {
+                    return Ok(());
+                } else {
+                    return Err(Error::StackUnderflow { context });
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether a value of type `got` may be used where `expected` is required.
+fn accepts(expected: &Type, got: &Type) -> bool {
+    match (expected, got) {
+        (Type::Any, _) | (_, Type::Any) => true,
+        (Type::Number, got) | (got, Type::Number) => is_number(got),
+        (Type::Vector, got) | (got, Type::Vector) => is_vector(got),
+        (Type::U32, Type::U8) | (Type::U8, Type::U32) => true,
+        (expected, got) => expected == got,
+    }
+}
+
+/// Returns whether the given type is a number.
+fn is_number(ty: &Type) -> bool {
+    matches!(ty, Type::F32 | Type::Number | Type::U32 | Type::U8)
+}
+
+/// Returns whether the given type is a vector.
+fn is_vector(ty: &Type) -> bool {
+    matches!(ty, Type::Vec2 | Type::Vec3 | Type::Vec4 | Type::Vector)
+}
+
+/// Returns the type of arithmetic on `a` and `b`, or `None` if they can't be combined.
+/// Vectors take precedence over floats, which take precedence over ints.
+fn promote(a: &Type, b: &Type) -> Option<Type> {
+    match (a, b) {
+        (Type::Vector, v) | (v, Type::Vector) if is_vector(v) => Some(v.clone()),
+        (v, w) if is_vector(v) && is_vector(w) => (v == w).then(|| v.clone()),
+        (v, n) | (n, v) if is_vector(v) && (is_number(n) || n == &Type::Any) => Some(v.clone()),
+        (Type::Any, n) | (n, Type::Any) if is_number(n) || n == &Type::Any => Some(Type::Any),
+        (Type::F32, n) | (n, Type::F32) if is_number(n) => Some(Type::F32),
+        (Type::Number, n) | (n, Type::Number) if is_number(n) => Some(Type::Number),
+        (a, b) if is_number(a) && is_number(b) => Some(Type::U32),
+        _ => None,
+    }
+}
+
+/// Returns the number of components of a vector type, if known.
+fn size(ty: &Type) -> Option<usize> {
+    match ty {
+        Type::Vec2 => Some(2),
+        Type::Vec3 => Some(3),
+        Type::Vec4 => Some(4),
+        _ => None,
+    }
+}
+
+/// Returns a type that both `a` and `b` may be treated as.
+fn unify(a: &Type, b: &Type) -> Type {
+    match (a, b) {
+        (a, b) if a == b => a.clone(),
+        (Type::U32, Type::U8) | (Type::U8, Type::U32) => Type::U32,
+        (a, b) if is_number(a) && is_number(b) => Type::Number,
+        (a, b) if is_vector(a) && is_vector(b) => Type::Vector,
+        _ => Type::Any,
+    }
+}
+
+/// A conditional or loop block that is being checked.
+#[derive(Debug)]
+struct Block {
+    /// The stacks a `while` loop may exit with, merged over every iteration simulated so far.
+    exit: Option<TypeStack>,
+    /// Where each iteration of a loop starts and the stack it starts with.
+    /// This is the condition of a `while` loop and the body of a `times` loop.
+    head: Option<(usize, TypeStack)>,
+    /// The inputs consumed when `do` is hit.
+    inputs: &'static [Type],
+    kind: BlockKind,
+    /// The stack from the other path through the block.
+    /// This is the skipped path until an `else` is hit, then the path through the `if` block.
+    other: Option<TypeStack>,
+}
+
+/// Walks through a program, simulating the types on the stack.
+struct Checker<'a> {
+    /// The words currently being simulated.
+    calls: Vec<usize>,
+    /// The stack each word left, keyed by its address and the stack it was called with,
+    /// so words called from many places are only simulated once per stack.
+    effects: HashMap<(usize, TypeStack), TypeStack>,
+    ops: &'a [Op],
+    spans: &'a [Span],
+}
+
+impl<'a> Checker<'a> {
+    /// Simulates the ops from `start` until the end of the program or the current word.
+    fn simulate(&mut self, stack: &mut TypeStack, start: usize) -> Result<(), Error> {
+        let mut blocks: Vec<Block> = vec![];
+
+        let mut next = start;
+        while let Some(op) = self.ops.get(next) {
+            let instruction_pointer = next;
+            next += 1;
+            let context = Context::new(op, self.spans.get(instruction_pointer));
+
+            match op {
+                Op::Add | Op::Divide | Op::Modulo | Op::Multiply | Op::Subtract => {
+                    let a = stack.pop(&context, &Type::Any)?;
+                    let b = stack.pop(&context, &Type::Any)?;
+                    let ty = match promote(&a.ty, &b.ty) {
+                        Some(ty) => ty,
+                        None => {
+                            // Blame `a` only if it can't be used in arithmetic at all.
+                            let got = match promote(&a.ty, &Type::Any) {
+                                Some(_) => b.ty,
+                                None => a.ty,
+                            };
+                            return Err(Error::TypeMismatch { context, got });
+                        }
+                    };
+
+                    stack.push(Slot { ty, value: None });
+                }
+                Op::Call(address) => {
+                    if self.calls.contains(address) {
+                        // A recursive word may leave anything on the stack.
+                        *stack = TypeStack {
+                            open: true,
+                            slots: vec![],
+                        };
+                    } else {
+                        let key = (*address, stack.clone());
+                        match self.effects.get(&key) {
+                            Some(effect) => *stack = effect.clone(),
+                            None => {
+                                self.calls.push(*address);
+                                self.simulate(stack, *address)?;
+                                self.calls.pop();
+                                self.effects.insert(key, stack.clone());
+                            }
+                        }
+                    }
+                }
+                Op::Data(data) => stack.push(Slot {
+                    ty: data.get_type(),
+                    value: match data {
+                        Data::U32(u) => Some(*u),
+                        Data::U8(u) => Some(*u as u32),
+                        _ => None,
+                    },
+                }),
+                Op::Do => {
+                    // The input of a block is consumed once `do` is hit.
+                    if let Some(block) = blocks.last_mut() {
+                        if block.other.is_none() {
+                            let context = Context {
+                                expected: block.inputs.to_vec(),
+                                ..context
+                            };
+                            stack.pop_all(&context, &context.expected)?;
+                            block.other = Some(stack.clone());
+                            if block.kind == BlockKind::Times {
+                                block.head = Some((next, stack.clone()));
+                            }
+                        }
+                    }
+                }
+                Op::Dup => {
+                    let a = stack.pop(&context, &Type::Any)?;
+                    stack.push(a.clone());
+                    stack.push(a);
+                }
+                Op::Else => {
+                    // The `else` block starts from the skipped stack.
+                    if let Some(block) = blocks.last_mut() {
+                        if let Some(skipped) = block.other.replace(stack.clone()) {
+                            *stack = skipped;
+                        }
+                    }
+                }
+                Op::End => match blocks.pop() {
+                    Some(Block {
+                        exit,
+                        head: Some((head, entry)),
+                        inputs,
+                        kind,
+                        other,
+                    }) => {
+                        // A `while` loop exits with the stack its condition leaves once it is false.
+                        let exit = match (kind, exit, other.clone()) {
+                            (BlockKind::While, Some(exit), Some(other)) => Some(exit.merge(&other)),
+                            (BlockKind::While, exit, other) => other.or(exit),
+                            _ => None,
+                        };
+
+                        // A loop may run any number of times, so the next iteration is simulated
+                        // from the stack the body leaves until the merged stack no longer changes.
+                        let merged = entry.merge(stack);
+                        if merged != entry {
+                            blocks.push(Block {
+                                exit,
+                                head: Some((head, merged)),
+                                inputs,
+                                kind,
+                                // The condition of a `while` loop consumes its input again.
+                                other: other.filter(|_| kind == BlockKind::Times),
+                            });
+                            next = head;
+                        } else {
+                            *stack = exit.unwrap_or(merged);
+                        }
+                    }
+                    Some(Block {
+                        other: Some(other), ..
+                    }) => *stack = stack.merge(&other),
+                    _ => {}
+                },
+                Op::If => blocks.push(Block {
+                    exit: None,
+                    head: None,
+                    inputs: op.required_inputs(),
+                    kind: BlockKind::If,
+                    other: None,
+                }),
+                Op::Normalize => {
+                    let v = stack.pop(&context, &Type::Vector)?;
+                    stack.push(Slot {
+                        ty: if v.ty == Type::Any {
+                            Type::Vector
+                        } else {
+                            v.ty
+                        },
+                        value: None,
+                    });
+                }
+                Op::Return => break,
+                Op::Rot => {
+                    let a = stack.pop(&context, &Type::Any)?;
+                    let b = stack.pop(&context, &Type::Any)?;
+                    stack.push(a);
+                    stack.push(b);
+                }
+                Op::RotN => {
+                    let n = stack.pop(&context, &Type::U32)?;
+                    match n.value {
+                        Some(0) => {}
+                        Some(n) if n as usize >= stack.slots.len() => {
+                            // The rotated values reach below the known slots, so any of them may be anything.
+                            if !stack.open {
+                                return Err(Error::StackUnderflow { context });
+                            }
+                            *stack = TypeStack {
+                                open: true,
+                                slots: vec![],
+                            };
+                        }
+                        Some(n) => {
+                            let a = stack.pop(&context, &Type::Any)?;
+                            let mut working_stack = vec![];
+                            for _ in 0..n - 1 {
+                                working_stack.push(stack.pop(&context, &Type::Any)?);
+                            }
+                            let b = stack.pop(&context, &Type::Any)?;
+
+                            stack.push(a);
+                            while let Some(slot) = working_stack.pop() {
+                                stack.push(slot);
+                            }
+                            stack.push(b);
+                        }
+                        None => {
+                            // Without knowing N any two values may have been swapped.
+                            for slot in stack.slots.iter_mut() {
+                                *slot = Slot::any();
+                            }
+                        }
+                    }
+                }
+                Op::SplitVec => {
+                    let v = stack.pop(&context, &Type::Vector)?;
+                    match size(&v.ty) {
+                        Some(size) => {
+                            for _ in 0..size {
+                                stack.push(Slot {
+                                    ty: Type::F32,
+                                    value: None,
+                                });
+                            }
+                        }
+                        None => {
+                            // Without knowing the size any number of floats may be pushed.
+                            *stack = TypeStack {
+                                open: true,
+                                slots: vec![],
+                            };
+                        }
+                    }
+                }
+                Op::Swizzle(components) => {
+                    let v = stack.pop(&context, &Type::Vector)?;
+                    let max = components.iter().max().copied().unwrap_or_default();
+                    if matches!(size(&v.ty), Some(size) if max >= size) {
+                        return Err(Error::TypeMismatch { context, got: v.ty });
+                    }
+
+                    for ty in op.outputs() {
+                        stack.push(Slot {
+                            ty: ty.clone(),
+                            value: None,
+                        });
+                    }
+                }
+                Op::Times => blocks.push(Block {
+                    exit: None,
+                    head: None,
+                    inputs: op.required_inputs(),
+                    kind: BlockKind::Times,
+                    other: None,
+                }),
+                Op::While => blocks.push(Block {
+                    exit: None,
+                    head: Some((next, stack.clone())),
+                    inputs: op.required_inputs(),
+                    kind: BlockKind::While,
+                    other: None,
+                }),
+                _ => {
+                    stack.pop_all(&context, op.required_inputs())?;
+                    for ty in op.outputs() {
+                        stack.push(Slot {
+                            ty: ty.clone(),
+                            value: None,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A value on the simulated stack.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Slot {
+    ty: Type,
+    /// The value, if it is a known number.
+    value: Option<u32>,
+}
+
+impl Slot {
+    /// Returns a slot that may contain anything.
+    fn any() -> Self {
+        Self {
+            ty: Type::Any,
+            value: None,
+        }
+    }
+}
+
+/// A simulated stack of types.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+struct TypeStack {
+    /// Whether there may be values of unknown types below the known slots.
+    open: bool,
+    slots: Vec<Slot>,
+}
+
+impl TypeStack {
+    /// Merges two stacks that control flow may join, aligning them by their tops.
+    fn merge(&self, other: &Self) -> Self {
+        let len = self.slots.len().min(other.slots.len());
+
+        let slots = self.slots[self.slots.len() - len..]
+            .iter()
+            .zip(other.slots[other.slots.len() - len..].iter())
+            .map(|(a, b)| Slot {
+                ty: unify(&a.ty, &b.ty),
+                value: if a.value == b.value { a.value } else { None },
+            })
+            .collect();
+
+        Self {
+            open: self.open || other.open || self.slots.len() != other.slots.len(),
+            slots,
+        }
+    }
+
+    /// Pops a slot that must be of the expected type.
+    fn pop(&mut self, context: &Context, expected: &Type) -> Result<Slot, Error> {
+        match self.slots.pop() {
+            Some(slot) => {
+                if accepts(expected, &slot.ty) {
+                    Ok(slot)
+                } else {
+                    Err(Error::TypeMismatch {
+                        context: context.clone(),
+                        got: slot.ty,
+                    })
+                }
+            }
+            None => {
+                if self.open {
+                    Ok(Slot::any())
+                } else {
+                    Err(Error::StackUnderflow {
+                        context: context.clone(),
+                    })
+                }
+            }
+        }
+    }
+
+    /// Pops all the given types, where the last type is the top of the stack.
+    fn pop_all(&mut self, context: &Context, types: &[Type]) -> Result<(), Error> {
+        for ty in types.iter().rev() {
+            self.pop(context, ty)?;
+        }
+
+        Ok(())
+    }
+
+    /// Pushes a slot onto the stack.
+    fn push(&mut self, slot: Slot) {
+        self.slots.push(slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel_machine::Program;
+
+    fn check_source(source: &str) -> Result<(), Error> {
+        Program::compile(source).unwrap().check(1)
+    }
+
+    fn context(op: Op, column: usize, len: usize) -> Context {
+        Context::new(
+            &op,
+            Some(&Span {
+                column,
+                file: 0,
+                len,
+                line: 1,
+            }),
+        )
+    }
+
+    mod accepts {
+        use super::*;
+
+        #[test]
+        fn any() {
+            assert_eq!(true, accepts(&Type::Any, &Type::Color));
+            assert_eq!(true, accepts(&Type::Bool, &Type::Any));
+        }
+
+        #[test]
+        fn floats_are_not_ints() {
+            assert_eq!(false, accepts(&Type::U32, &Type::F32));
+            assert_eq!(false, accepts(&Type::F32, &Type::U8));
+        }
+
+        #[test]
+        fn mismatch() {
+            assert_eq!(false, accepts(&Type::Bool, &Type::Color));
+            assert_eq!(false, accepts(&Type::U32, &Type::String));
+            assert_eq!(false, accepts(&Type::Number, &Type::Bool));
+        }
+
+        #[test]
+        fn numbers() {
+            assert_eq!(true, accepts(&Type::U32, &Type::U8));
+            assert_eq!(true, accepts(&Type::U8, &Type::U32));
+            assert_eq!(true, accepts(&Type::Number, &Type::F32));
+            assert_eq!(true, accepts(&Type::U8, &Type::Number));
+        }
+    }
+
+    mod check {
+        use super::*;
+
+        #[test]
+        fn empty_underflows() {
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context {
+                        expected: vec![Type::Color],
+                        op: "".into(),
+                        span: None,
+                    }
+                }),
+                check(0, &[], 1, &[])
+            );
+        }
+
+        #[test]
+        fn floats_need_conversion() {
+            assert_eq!(
+                Err(Error::TypeMismatch {
+                    context: context(Op::MakeColor, 22, 9),
+                    got: Type::F32,
+                }),
+                check_source("1 2 3 0.5 255 toU8 * makeColor")
+            );
+
+            assert_eq!(Ok(()), check_source("1 2 3 0.5 255 toU8 * toU8 makeColor"));
+        }
+
+        #[test]
+        fn if_blocks_merge() {
+            assert_eq!(Ok(()), check_source("1 if true do 2 3 end 4 5 6 makeColor"));
+        }
+
+        #[test]
+        fn if_else_merge() {
+            assert_eq!(
+                Ok(()),
+                check_source("1 2 3 if true do 4 else 5 end makeColor")
+            );
+        }
+
+        #[test]
+        fn if_else_starts_from_skipped() {
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: context(Op::Drop, 19, 4),
+                }),
+                check_source("if true do 1 else drop end")
+            );
+        }
+
+        #[test]
+        fn if_requires_bool() {
+            assert_eq!(
+                Err(Error::TypeMismatch {
+                    context: Context {
+                        expected: vec![Type::Bool],
+                        ..context(Op::Do, 6, 2)
+                    },
+                    got: Type::U8,
+                }),
+                check_source("if 1 do end 1 2 3 4 makeColor")
+            );
+        }
+
+        #[test]
+        fn if_different_heights_is_lenient() {
+            assert_eq!(Ok(()), check_source("if false do 1 2 3 4 makeColor end"));
+        }
+
+        #[test]
+        fn invalid_result() {
+            assert_eq!(
+                Err(Error::InvalidResult {
+                    context: Context {
+                        expected: vec![Type::Color],
+                        ..context(Op::Data(Data::U8(4)), 7, 1)
+                    },
+                    got: Type::U8
+                }),
+                check_source("1 2 3 4")
+            );
+        }
+
+        #[test]
+        fn invalid_type() {
+            assert_eq!(
+                Err(Error::TypeMismatch {
+                    context: context(Op::Add, 8, 1),
+                    got: Type::Bool,
+                }),
+                check_source("true 2 +")
+            );
+        }
+
+        #[test]
+        fn loops_consume_inputs() {
+            assert_eq!(
+                Ok(()),
+                check_source("1 2 3 4 times 4 do index drop end while false do end makeColor")
+            );
+        }
+
+        #[test]
+        fn loops_require_inputs() {
+            assert_eq!(
+                Err(Error::TypeMismatch {
+                    context: Context {
+                        expected: vec![Type::U32],
+                        ..context(Op::Do, 12, 2)
+                    },
+                    got: Type::Bool,
+                }),
+                check_source("times true do end")
+            );
+
+            assert_eq!(
+                Err(Error::TypeMismatch {
+                    context: Context {
+                        expected: vec![Type::Bool],
+                        ..context(Op::Do, 9, 2)
+                    },
+                    got: Type::U8,
+                }),
+                check_source("while 1 do end")
+            );
+        }
+
+        #[test]
+        fn loops_recheck_condition() {
+            assert_eq!(
+                Err(Error::TypeMismatch {
+                    context: Context {
+                        expected: vec![Type::Number, Type::Number],
+                        ..context(Op::LessThan, 15, 1)
+                    },
+                    got: Type::Bool,
+                }),
+                check_source("1 while dup 5 < do drop true end drop 0 0 0 255 makeColor")
+            );
+
+            assert_eq!(
+                Err(Error::TypeMismatch {
+                    context: Context {
+                        expected: vec![Type::Number, Type::Number],
+                        ..context(Op::Add, 16, 1)
+                    },
+                    got: Type::Bool,
+                }),
+                check_source("1 times 2 do 1 + drop true end drop 0 0 0 255 makeColor")
+            );
+        }
+
+        #[test]
+        fn loops_converge() {
+            assert_eq!(
+                Ok(()),
+                check_source("0 while dup 10 < do 1 + end 0.5 * toU8 dup dup 255 makeColor")
+            );
+            assert_eq!(
+                Ok(()),
+                check_source("0 0 0 255 makeColor times 4 do dup end")
+            );
+        }
+
+        #[test]
+        fn multiple_outputs() {
+            let program = Program::compile("1 2 3 4 makeColor 5 6 7 8 makeColor").unwrap();
+            assert_eq!(Ok(()), program.check(2));
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context {
+                        expected: vec![Type::Color; 3],
+                        ..context(Op::MakeColor, 27, 9)
+                    }
+                }),
+                program.check(3)
+            );
+
+            assert_eq!(
+                Err(Error::InvalidResult {
+                    context: Context {
+                        expected: vec![Type::Color; 2],
+                        ..context(Op::MakeColor, 11, 9)
+                    },
+                    got: Type::U8,
+                }),
+                Program::compile("1 1 2 3 4 makeColor").unwrap().check(2)
+            );
+        }
+
+        #[test]
+        fn rot_n_known() {
+            assert_eq!(Ok(()), check_source("1 2 3 4 makeColor 5 true 2 rotN"));
+
+            assert_eq!(
+                Err(Error::InvalidResult {
+                    context: Context {
+                        expected: vec![Type::Color],
+                        ..context(Op::RotN, 26, 4)
+                    },
+                    got: Type::Bool
+                }),
+                check_source("true 1 2 3 4 makeColor 1 rotN")
+            );
+        }
+
+        #[test]
+        fn rot_n_past_known_slots() {
+            assert_eq!(
+                Ok(()),
+                check_source(": open open ; open 1 2 3 4 makeColor 4000000000 rotN")
+            );
+
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: context(Op::RotN, 21, 4),
+                }),
+                check_source("1 2 3 4 makeColor 1 rotN")
+            );
+        }
+
+        #[test]
+        fn rot_n_unknown() {
+            assert_eq!(
+                Ok(()),
+                check_source("1 2 3 4 makeColor true fragPos + rotN")
+            );
+        }
+
+        #[test]
+        fn split_vec() {
+            assert_eq!(
+                Ok(()),
+                check_source("0.5 0.5 vec2 splitVec + toU8 dup dup dup makeColor")
+            );
+        }
+
+        #[test]
+        fn strings() {
+            assert_eq!(Ok(()), check_source(r#"0 0 "mummy" texture texturePixel"#));
+            assert_eq!(
+                Err(Error::TypeMismatch {
+                    context: context(Op::TextureIndex, 3, 7),
+                    got: Type::U8,
+                }),
+                check_source("0 texture")
+            );
+        }
+
+        #[test]
+        fn swizzle_out_of_range() {
+            assert_eq!(
+                Err(Error::TypeMismatch {
+                    context: context(Op::Swizzle(vec![0, 2]), 10, 3),
+                    got: Type::Vec2,
+                }),
+                check_source("1 2 vec2 .xz")
+            );
+        }
+
+        #[test]
+        fn underflow() {
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: context(Op::MakeColor, 7, 9),
+                }),
+                check_source("1 2 3 makeColor")
+            );
+        }
+
+        #[test]
+        fn valid() {
+            assert_eq!(
+                Ok(()),
+                check_source("fragPos 0 texturePixel splitColor rot drop 255 makeColor")
+            );
+        }
+
+        #[test]
+        fn vectors() {
+            assert_eq!(
+                Ok(()),
+                check_source(
+                    "fragPos vec2 dim vec2 / splitVec 0.0 1.0 vec4 .xyxw normalize 2 * toColor"
+                )
+            );
+        }
+
+        #[test]
+        fn vectors_mismatch() {
+            assert_eq!(
+                Err(Error::TypeMismatch {
+                    context: context(Op::Add, 21, 1),
+                    got: Type::Vec2,
+                }),
+                check_source("1 2 vec2 1 2 3 vec3 +")
+            );
+        }
+
+        #[test]
+        fn words() {
+            assert_eq!(
+                Ok(()),
+                check_source(": gray dup dup 255 makeColor ; 128 gray")
+            );
+
+            assert_eq!(
+                Err(Error::TypeMismatch {
+                    context: context(Op::MakeColor, 20, 9),
+                    got: Type::Bool,
+                }),
+                check_source(": gray dup dup 255 makeColor ; true gray")
+            );
+        }
+
+        #[test]
+        fn words_called_exponentially() {
+            let mut source = ": w0 dup drop ;".to_string();
+            for idx in 1..64 {
+                source.push_str(&format!(" : w{} w{} w{} ;", idx, idx - 1, idx - 1));
+            }
+            source.push_str(" 1 2 3 4 makeColor w63");
+
+            assert_eq!(Ok(()), check_source(&source));
+        }
+
+        #[test]
+        fn words_recursive_are_lenient() {
+            assert_eq!(
+                Ok(()),
+                check_source(
+                    ": countdown if dup 0 > do 1 - countdown end ; 1 2 3 4 makeColor 5 countdown"
+                )
+            );
+        }
+    }
+
+    mod merge {
+        use super::*;
+
+        fn stack(types: &[Type]) -> TypeStack {
+            TypeStack {
+                open: false,
+                slots: types
+                    .iter()
+                    .map(|ty| Slot {
+                        ty: ty.clone(),
+                        value: None,
+                    })
+                    .collect(),
+            }
+        }
+
+        #[test]
+        fn different_heights_opens() {
+            let a = stack(&[Type::Bool, Type::Color]);
+            let b = stack(&[Type::Color]);
+            assert_eq!(
+                TypeStack {
+                    open: true,
+                    slots: vec![Slot {
+                        ty: Type::Color,
+                        value: None
+                    }]
+                },
+                a.merge(&b)
+            );
+        }
+
+        #[test]
+        fn different_types() {
+            let a = stack(&[Type::Bool, Type::U8]);
+            let b = stack(&[Type::Color, Type::U32]);
+            assert_eq!(stack(&[Type::Any, Type::U32]), a.merge(&b));
+        }
+
+        #[test]
+        fn same() {
+            let a = stack(&[Type::Bool, Type::Color]);
+            assert_eq!(a, a.merge(&a));
+        }
+    }
+
+    mod promote {
+        use super::*;
+
+        #[test]
+        fn floats() {
+            assert_eq!(Some(Type::F32), promote(&Type::F32, &Type::U8));
+            assert_eq!(Some(Type::F32), promote(&Type::Number, &Type::F32));
+        }
+
+        #[test]
+        fn ints() {
+            assert_eq!(Some(Type::U32), promote(&Type::U8, &Type::U8));
+            assert_eq!(Some(Type::U32), promote(&Type::U32, &Type::U8));
+        }
+
+        #[test]
+        fn mismatch() {
+            assert_eq!(None, promote(&Type::Bool, &Type::U8));
+            assert_eq!(None, promote(&Type::Vec2, &Type::Vec3));
+            assert_eq!(None, promote(&Type::Any, &Type::Color));
+        }
+
+        #[test]
+        fn unknown() {
+            assert_eq!(Some(Type::Any), promote(&Type::Any, &Type::U32));
+            assert_eq!(Some(Type::Number), promote(&Type::Number, &Type::U32));
+        }
+
+        #[test]
+        fn vectors() {
+            assert_eq!(Some(Type::Vec3), promote(&Type::Vec3, &Type::Vec3));
+            assert_eq!(Some(Type::Vec3), promote(&Type::F32, &Type::Vec3));
+            assert_eq!(Some(Type::Vec4), promote(&Type::Vec4, &Type::Any));
+            assert_eq!(Some(Type::Vec2), promote(&Type::Vector, &Type::Vec2));
+        }
+    }
+}
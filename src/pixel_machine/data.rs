@@ -3,33 +3,90 @@
 pub enum Data {
     Bool(bool),
     Color(Color),
+    F32(f32),
     String(String),
     U32(u32),
     U8(u8),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
 }
 
 impl Data {
+    /// Returns the components of a vector.
+    pub fn components(&self) -> Option<&[f32]> {
+        match self {
+            Data::Vec2(v) => Some(v),
+            Data::Vec3(v) => Some(v),
+            Data::Vec4(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Creates a float or vector from the given components.
+    pub fn from_components(components: &[f32]) -> Option<Self> {
+        match *components {
+            [x] => Some(Data::F32(x)),
+            [x, y] => Some(Data::Vec2([x, y])),
+            [x, y, z] => Some(Data::Vec3([x, y, z])),
+            [x, y, z, w] => Some(Data::Vec4([x, y, z, w])),
+            _ => None,
+        }
+    }
+
     /// Returns the type for the data
     pub fn get_type(&self) -> Type {
         match self {
             Data::Bool(_) => Type::Bool,
             Data::Color(_) => Type::Color,
+            Data::F32(_) => Type::F32,
             Data::U32(_) => Type::U32,
             Data::String(_) => Type::String,
             Data::U8(_) => Type::U8,
+            Data::Vec2(_) => Type::Vec2,
+            Data::Vec3(_) => Type::Vec3,
+            Data::Vec4(_) => Type::Vec4,
+        }
+    }
+
+    /// Attempts to parse a literal, such as `4`, `70000`, `0.5` or `true`.
+    /// Floats must contain a `.` to tell them apart from integers.
+    pub fn parse(token: &str) -> Option<Self> {
+        if let Ok(u) = token.parse::<u8>() {
+            Some(Data::U8(u))
+        } else if let Ok(u) = token.parse::<u32>() {
+            Some(Data::U32(u))
+        } else if let Some(f) = token
+            .contains('.')
+            .then(|| token.parse::<f32>().ok())
+            .flatten()
+        {
+            Some(Data::F32(f))
+        } else if let Ok(b) = token.parse::<bool>() {
+            Some(Data::Bool(b))
+        } else {
+            None
         }
     }
 }
 
 /// The various types used in the PixelMachine.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub enum Type {
     Any,
     Bool,
     Color,
+    F32,
+    /// Any of `F32`, `U32` or `U8`.
+    Number,
     String,
     U32,
     U8,
+    Vec2,
+    Vec3,
+    Vec4,
+    /// Any of `Vec2`, `Vec3` or `Vec4`.
+    Vector,
 }
 
 /// A color.
@@ -64,6 +121,43 @@ mod tests {
     mod data {
         use super::*;
 
+        mod components {
+            use super::*;
+
+            #[test]
+            fn not_vector() {
+                assert_eq!(None, Data::F32(1.0).components());
+            }
+
+            #[test]
+            fn vector() {
+                let expected: &[f32] = &[1.0, 2.0, 3.0];
+                assert_eq!(Some(expected), Data::Vec3([1.0, 2.0, 3.0]).components());
+            }
+        }
+
+        mod from_components {
+            use super::*;
+
+            #[test]
+            fn empty() {
+                assert_eq!(None, Data::from_components(&[]));
+            }
+
+            #[test]
+            fn float() {
+                assert_eq!(Some(Data::F32(1.0)), Data::from_components(&[1.0]));
+            }
+
+            #[test]
+            fn vector() {
+                assert_eq!(
+                    Some(Data::Vec4([1.0, 2.0, 3.0, 4.0])),
+                    Data::from_components(&[1.0, 2.0, 3.0, 4.0])
+                );
+            }
+        }
+
         mod get_type {
             use super::*;
 
@@ -79,6 +173,12 @@ mod tests {
                 assert_eq!(Type::Color, d.get_type());
             }
 
+            #[test]
+            fn get_type_f32() {
+                let d = Data::F32(0.5);
+                assert_eq!(Type::F32, d.get_type());
+            }
+
             #[test]
             fn get_type_string() {
                 let d = Data::String("garbage".into());
@@ -96,6 +196,38 @@ mod tests {
                 let d = Data::U8(22);
                 assert_eq!(Type::U8, d.get_type());
             }
+
+            #[test]
+            fn get_type_vec2() {
+                let d = Data::Vec2([0.0, 1.0]);
+                assert_eq!(Type::Vec2, d.get_type());
+            }
+        }
+
+        mod parse {
+            use super::*;
+
+            #[test]
+            fn bool() {
+                assert_eq!(Some(Data::Bool(true)), Data::parse("true"));
+            }
+
+            #[test]
+            fn float() {
+                assert_eq!(Some(Data::F32(0.5)), Data::parse("0.5"));
+                assert_eq!(None, Data::parse("1e3"));
+            }
+
+            #[test]
+            fn integers() {
+                assert_eq!(Some(Data::U8(255)), Data::parse("255"));
+                assert_eq!(Some(Data::U32(256)), Data::parse("256"));
+            }
+
+            #[test]
+            fn invalid() {
+                assert_eq!(None, Data::parse("dup"));
+            }
         }
     }
 }
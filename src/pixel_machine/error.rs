@@ -0,0 +1,423 @@
+use super::{Data, Op, PixelMachine, Span, Type};
+use std::fmt;
+
+/// Various errors that may occur.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// A number was attempted to be divided by zero.
+    DivideByZero { context: Context },
+    /// An included file could not be read or was included in a cycle.
+    InvalidInclude {
+        context: Context,
+        /// Why the file could not be included.
+        reason: String,
+    },
+    /// The program did not leave a color on top of the stack.
+    InvalidResult {
+        context: Context,
+        /// The type that was left on top of the stack.
+        got: Type,
+    },
+    /// A string literal could not be parsed.
+    InvalidString {
+        context: Context,
+        /// Why the string was invalid.
+        reason: &'static str,
+    },
+    /// A texture was read that was not loaded.
+    InvalidTexture {
+        context: Context,
+        /// The index of the texture.
+        index: u32,
+        /// The number of loaded textures.
+        len: usize,
+    },
+    /// An invalid type was provided.
+    InvalidType {
+        context: Context,
+        /// The data that was popped off the stack.
+        got: Data,
+    },
+    /// A word definition was invalid.
+    InvalidWord {
+        context: Context,
+        /// Why the definition was invalid.
+        reason: &'static str,
+    },
+    /// Loops executed more iterations than allowed.
+    IterationLimit {
+        context: Context,
+        /// The maximum number of iterations.
+        limit: u32,
+    },
+    /// An op that requires a loop was executed outside of one.
+    NotInLoop { context: Context },
+    /// Words called each other deeper than allowed.
+    RecursionLimit {
+        context: Context,
+        /// The maximum depth of calls.
+        limit: usize,
+    },
+    /// The stack was empty and a value was attempted to be popped off.
+    StackUnderflow { context: Context },
+    /// An op was statically determined to receive the wrong type.
+    TypeMismatch {
+        context: Context,
+        /// The type that would be on the stack.
+        got: Type,
+    },
+    /// A texture was looked up by a name that no input has.
+    UndefinedTexture {
+        context: Context,
+        /// The name of the texture.
+        name: String,
+    },
+    /// A uniform was read that the config does not define.
+    UndefinedUniform {
+        context: Context,
+        /// The name of the uniform.
+        name: String,
+    },
+    /// A block was not closed or an op was outside of a block.
+    UnbalancedBlock {
+        context: Context,
+        /// The op that is missing.
+        missing: &'static str,
+    },
+    /// There was an unhandled token.
+    UnhandledToken {
+        context: Context,
+        /// The token that could not be parsed.
+        got: String,
+    },
+}
+
+impl Error {
+    /// Returns the context the error occurred in.
+    pub fn context(&self) -> &Context {
+        match self {
+            Error::DivideByZero { context }
+            | Error::InvalidInclude { context, .. }
+            | Error::InvalidResult { context, .. }
+            | Error::InvalidString { context, .. }
+            | Error::InvalidTexture { context, .. }
+            | Error::InvalidType { context, .. }
+            | Error::InvalidWord { context, .. }
+            | Error::IterationLimit { context, .. }
+            | Error::NotInLoop { context }
+            | Error::RecursionLimit { context, .. }
+            | Error::StackUnderflow { context }
+            | Error::TypeMismatch { context, .. }
+            | Error::UndefinedTexture { context, .. }
+            | Error::UndefinedUniform { context, .. }
+            | Error::UnbalancedBlock { context, .. }
+            | Error::UnhandledToken { context, .. } => context,
+        }
+    }
+
+    /// Returns a mutable reference to the context the error occurred in.
+    fn context_mut(&mut self) -> &mut Context {
+        match self {
+            Error::DivideByZero { context }
+            | Error::InvalidInclude { context, .. }
+            | Error::InvalidResult { context, .. }
+            | Error::InvalidString { context, .. }
+            | Error::InvalidTexture { context, .. }
+            | Error::InvalidType { context, .. }
+            | Error::InvalidWord { context, .. }
+            | Error::IterationLimit { context, .. }
+            | Error::NotInLoop { context }
+            | Error::RecursionLimit { context, .. }
+            | Error::StackUnderflow { context }
+            | Error::TypeMismatch { context, .. }
+            | Error::UndefinedTexture { context, .. }
+            | Error::UndefinedUniform { context, .. }
+            | Error::UnbalancedBlock { context, .. }
+            | Error::UnhandledToken { context, .. } => context,
+        }
+    }
+
+    /// Renders the error as a compiler style diagnostic for the given source.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let context = self.context();
+        let mut rendered = format!("error: {}\n", self);
+
+        let gutter = match &context.span {
+            Some(span) => {
+                let gutter = " ".repeat(span.line.to_string().len());
+                let line = source
+                    .lines()
+                    .nth(span.line.saturating_sub(1))
+                    .unwrap_or_default()
+                    .replace('\t', " ");
+
+                rendered += &format!("{}--> {}:{}:{}\n", gutter, path, span.line, span.column);
+                rendered += &format!("{} |\n", gutter);
+                rendered += &format!("{} | {}\n", span.line, line);
+                rendered += &format!(
+                    "{} | {}{}\n",
+                    gutter,
+                    " ".repeat(span.column.saturating_sub(1)),
+                    "^".repeat(span.len.max(1))
+                );
+
+                gutter
+            }
+            None => {
+                rendered += &format!("--> {}\n", path);
+                String::new()
+            }
+        };
+
+        if let Ok(op) = PixelMachine::parse(&context.op) {
+            rendered += &format!("{} = note: `{}`\n", gutter, op.signature());
+        }
+
+        rendered
+    }
+
+    /// Fills in the op the error occurred on, if not already set.
+    pub(crate) fn with_op(mut self, expected: &[Type], op: &str) -> Self {
+        let context = self.context_mut();
+        if context.op.is_empty() {
+            context.expected = expected.to_vec();
+            context.op = op.into();
+        }
+
+        self
+    }
+
+    /// Fills in the span the error occurred on, if not already set.
+    pub(crate) fn with_span(mut self, span: Option<&Span>) -> Self {
+        let context = self.context_mut();
+        if context.span.is_none() {
+            context.span = span.copied();
+        }
+
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let context = self.context();
+        match self {
+            Error::DivideByZero { .. } => write!(f, "`{}` attempted to divide by zero", context.op),
+            Error::InvalidInclude { reason, .. } => write!(f, "{}", reason),
+            Error::InvalidResult { got, .. } => write!(
+                f,
+                "expected a Color on top of the stack when the program ends, got {:?}",
+                got
+            ),
+            Error::InvalidString { reason, .. } => write!(f, "string {} {}", context.op, reason),
+            Error::InvalidTexture { index, len, .. } => write!(
+                f,
+                "`{}` read texture {}, but only {} inputs are loaded",
+                context.op, index, len
+            ),
+            Error::InvalidType { got, .. } => write!(
+                f,
+                "`{}` expected {:?}, got {:?}",
+                context.op, context.expected, got
+            ),
+            Error::InvalidWord { reason, .. } => write!(f, "`{}` {}", context.op, reason),
+            Error::IterationLimit { limit, .. } => write!(
+                f,
+                "`{}` exceeded the limit of {} loop iterations",
+                context.op, limit
+            ),
+            Error::NotInLoop { .. } => write!(f, "`{}` must be used inside of a loop", context.op),
+            Error::RecursionLimit { limit, .. } => {
+                write!(f, "exceeded the limit of {} nested calls", limit)
+            }
+            Error::StackUnderflow { .. } => write!(
+                f,
+                "stack underflow, `{}` expected {:?}",
+                context.op, context.expected
+            ),
+            Error::TypeMismatch { got, .. } => write!(
+                f,
+                "`{}` expected {:?}, got {:?}",
+                context.op, context.expected, got
+            ),
+            Error::UndefinedTexture { name, .. } => {
+                write!(f, "no input is named `{}`", name)
+            }
+            Error::UndefinedUniform { name, .. } => {
+                write!(f, "uniform `{}` is not defined in the cfg", name)
+            }
+            Error::UnbalancedBlock { missing, .. } => {
+                write!(f, "`{}` is missing a matching `{}`", context.op, missing)
+            }
+            Error::UnhandledToken { got, .. } => write!(f, "unhandled token `{}`", got),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Where an error occurred.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Context {
+    /// The types the op expected on the stack.
+    pub expected: Vec<Type>,
+    /// The name of the op.
+    pub op: String,
+    /// The location of the op in the source.
+    pub span: Option<Span>,
+}
+
+impl Context {
+    /// Creates a new context for the given op.
+    pub fn new(op: &Op, span: Option<&Span>) -> Self {
+        Self {
+            expected: op.required_inputs().to_vec(),
+            op: op.name().into(),
+            span: span.copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(column: usize, len: usize, line: usize) -> Option<Span> {
+        Some(Span {
+            column,
+            file: 0,
+            len,
+            line,
+        })
+    }
+
+    mod render {
+        use super::*;
+
+        #[test]
+        fn no_span() {
+            let error = Error::StackUnderflow {
+                context: Context::new(&Op::Drop, None),
+            };
+
+            assert_eq!(
+                "error: stack underflow, `drop` expected [Any]
+--> test.das
+ = note: `drop = [A] -> []`
+",
+                error.render("test.das", "drop")
+            );
+        }
+
+        #[test]
+        fn snippet() {
+            let error = Error::InvalidType {
+                context: Context {
+                    expected: vec![Type::U32, Type::U32, Type::U32],
+                    op: "texturePixel".into(),
+                    span: span(11, 12, 2),
+                },
+                got: Data::Bool(true),
+            };
+
+            assert_eq!(
+                "error: `texturePixel` expected [U32, U32, U32], got Bool(true)
+ --> test.das:2:11
+  |
+2 | fragPos 0 texturePixel
+  |           ^^^^^^^^^^^^
+  = note: `texturePixel = [x:u32 y:u32 textureIdx:u32] -> [color]`
+",
+                error.render("test.das", "# Comment\r\nfragPos 0\ttexturePixel\n")
+            );
+        }
+
+        #[test]
+        fn unhandled_token() {
+            let error = Error::UnhandledToken {
+                context: Context {
+                    expected: vec![],
+                    op: "garbage".into(),
+                    span: span(3, 7, 1),
+                },
+                got: "garbage".into(),
+            };
+
+            assert_eq!(
+                "error: unhandled token `garbage`
+ --> test.das:1:3
+  |
+1 | 1 garbage
+  |   ^^^^^^^
+",
+                error.render("test.das", "1 garbage")
+            );
+        }
+    }
+
+    mod with_op {
+        use super::*;
+
+        #[test]
+        fn empty_sets_op() {
+            let error = Error::DivideByZero {
+                context: Context::default(),
+            };
+
+            assert_eq!(
+                Error::DivideByZero {
+                    context: Context {
+                        expected: vec![Type::U32, Type::U32],
+                        op: "/".into(),
+                        span: None,
+                    }
+                },
+                error.with_op(&[Type::U32, Type::U32], "/")
+            );
+        }
+
+        #[test]
+        fn set_keeps_op() {
+            let context = Context::new(&Op::Modulo, None);
+            let error = Error::DivideByZero {
+                context: context.clone(),
+            };
+
+            assert_eq!(
+                Error::DivideByZero { context },
+                error.with_op(&[Type::U32, Type::U32], "/")
+            );
+        }
+    }
+
+    mod with_span {
+        use super::*;
+
+        #[test]
+        fn empty_sets_span() {
+            let error = Error::StackUnderflow {
+                context: Context::default(),
+            };
+
+            assert_eq!(
+                span(1, 2, 3),
+                error.with_span(span(1, 2, 3).as_ref()).context().span
+            );
+        }
+
+        #[test]
+        fn set_keeps_span() {
+            let error = Error::StackUnderflow {
+                context: Context {
+                    span: span(4, 5, 6),
+                    ..Context::default()
+                },
+            };
+
+            assert_eq!(
+                span(4, 5, 6),
+                error.with_span(span(1, 2, 3).as_ref()).context().span
+            );
+        }
+    }
+}
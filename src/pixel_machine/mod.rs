@@ -1,63 +1,238 @@
+mod checker;
 mod data;
+mod error;
 mod op;
+mod optimizer;
+mod program;
+mod texture;
 
+use std::sync::Arc;
 use std::u8;
 
 pub use data::*;
+pub use error::*;
 pub use op::*;
+pub use program::*;
+pub use texture::*;
 
-use crate::Texture;
 use game_utils::collections::Stack;
-use image::GenericImageView;
-
-/// Various errors that may occur.
-#[derive(Debug, PartialEq)]
-pub enum Error {
-    /// A number was attempted to be divided by zero.
-    DivideByZero,
-    /// An invalid type was provided.
-    /// TODO: somehow link to required inputs off of Ops.
-    InvalidType {
-        /// The data that was popped off the stack
-        got: Data,
-        /// The instruction that this occurred on
-        instruction_pointer: usize,
-    },
-    /// The stack was empty and a value was attempted to be popped off.
-    /// TODO: somehow link to required inputs off of Ops.
-    StackUnderflow,
-    /// There was an unhandled token.
-    UnhandledToken { got: String },
+
+/// The maximum depth of nested word calls a pixel machine may execute.
+pub const MAX_CALL_DEPTH: usize = 256;
+
+/// The maximum number of loop iterations a pixel machine may execute.
+pub const MAX_ITERATIONS: u32 = 1_000_000;
+
+/// The top two numbers of the stack, promoted to floats if either is one.
+#[derive(Debug, Clone, PartialEq)]
+enum Numbers {
+    F32(f32, f32),
+    U32(u32, u32),
+}
+
+/// Returns the length of the given vector.
+fn length(v: &[f32]) -> f32 {
+    v.iter().map(|c| c * c).sum::<f32>().sqrt()
+}
+
+/// Parses a sample op such as `sampleBilinear` or `sampleNearest.clamp`.
+fn parse_sample(token: &str) -> Option<Op> {
+    let (name, address) = match token.split_once('.') {
+        Some((name, address)) => (name, Some(Address::parse(address)?)),
+        None => (token, None),
+    };
+
+    let filter = match name {
+        "sampleBicubic" => Filter::Bicubic,
+        "sampleBilinear" => Filter::Bilinear,
+        "sampleNearest" => Filter::Nearest,
+        _ => return None,
+    };
+
+    Some(Op::Sample { address, filter })
+}
+
+/// Parses a quoted string literal, replacing its escapes.
+/// Returns why the string is invalid on failure.
+fn parse_string(token: &str) -> Result<String, &'static str> {
+    let contents = match token.strip_prefix('"') {
+        Some(contents) => contents,
+        None => return Err("is missing an opening quote"),
+    };
+
+    let mut string = String::new();
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                return match chars.next() {
+                    Some(_) => Err("has characters after the closing quote"),
+                    None => Ok(string),
+                };
+            }
+            '\\' => match chars.next() {
+                Some('"') => string.push('"'),
+                Some('\\') => string.push('\\'),
+                Some('n') => string.push('\n'),
+                Some('r') => string.push('\r'),
+                Some('t') => string.push('\t'),
+                _ => return Err("has an unknown escape"),
+            },
+            c => string.push(c),
+        }
+    }
+
+    Err("is missing a closing quote")
+}
+
+/// Parses a swizzle such as `.xy` or `.bgra` into the indices of its components.
+fn parse_swizzle(token: &str) -> Option<Vec<usize>> {
+    let components = token.strip_prefix('.')?;
+    if components.is_empty() || components.len() > 4 {
+        return None;
+    }
+
+    ["xyzw", "rgba"].iter().find_map(|set| {
+        components
+            .chars()
+            .map(|c| set.find(c))
+            .collect::<Option<Vec<usize>>>()
+    })
 }
 
 /// A virtual machine that operates on a pixel.
 #[derive(Debug, Clone, PartialEq)]
 pub struct PixelMachine {
+    /// The addresses to return to from the words currently executing.
+    calls: Vec<usize>,
+    /// The index of the frame being rendered.
+    frame: u32,
     instruction_pointer: usize,
-    instructions: Vec<Op>,
+    /// The number of loop iterations executed so far.
+    iterations: u32,
+    /// The counters of the `times` loops currently executing.
+    loops: Vec<LoopCounter>,
+    program: Program,
     stack: Stack<Data>,
-    textures: Vec<Texture>,
+    textures: Vec<Arc<Texture>>,
+    /// The elapsed time of the frame being rendered in seconds.
+    time: f32,
     width: u32,
     height: u32,
     x: u32,
     y: u32,
 }
 impl PixelMachine {
-    /// TODO: test
+    /// Applies an arithmetic op to the top two values, where the top is the first argument.
+    /// Vectors are operated on component-wise, with numbers applied to every component.
+    /// Returning `None` signals a division by zero.
+    fn arithmetic(
+        &mut self,
+        floats: fn(f32, f32) -> Option<f32>,
+        ints: fn(u32, u32) -> Option<u32>,
+    ) -> Result<(), Error> {
+        let divide_by_zero = || Error::DivideByZero {
+            context: Context::default(),
+        };
+
+        let a = self.pop()?;
+        let b = self.pop()?;
+
+        let len = match (a.components(), b.components()) {
+            (None, None) => {
+                self.push(b)?;
+                self.push(a)?;
+
+                let data = match self.pop_numbers()? {
+                    Numbers::F32(a, b) => Data::F32(floats(a, b).ok_or_else(divide_by_zero)?),
+                    Numbers::U32(a, b) => Data::U32(ints(a, b).ok_or_else(divide_by_zero)?),
+                };
+
+                return self.push(data);
+            }
+            (Some(v), Some(w)) if v.len() != w.len() => None,
+            (Some(v), _) | (_, Some(v)) => Some(v.len()),
+        };
+
+        let len = match len {
+            Some(len) => len,
+            None => return Err(self.invalid_type(b)),
+        };
+
+        let a = self.broadcast(&a, len)?;
+        let b = self.broadcast(&b, len)?;
+
+        let mut v = Vec::with_capacity(len);
+        for (a, b) in a.into_iter().zip(b) {
+            v.push(floats(a, b).ok_or_else(divide_by_zero)?);
+        }
+
+        self.push_vector(&v)
+    }
+
+    /// Returns the components of a vector, or a number repeated `len` times.
+    fn broadcast(&self, data: &Data, len: usize) -> Result<Vec<f32>, Error> {
+        match data {
+            Data::F32(f) => Ok(vec![*f; len]),
+            Data::U32(u) => Ok(vec![*u as f32; len]),
+            Data::U8(u) => Ok(vec![*u as f32; len]),
+            data => data
+                .components()
+                .map(|v| v.to_vec())
+                .ok_or_else(|| self.invalid_type(data.clone())),
+        }
+    }
+
+    /// Returns the block of the op that is currently executing.
+    fn current_block(&self) -> Option<Block> {
+        self.instruction_pointer
+            .checked_sub(1)
+            .and_then(|idx| self.program.block(idx))
+    }
+
+    /// Executes the given op.
     pub fn execute(&mut self, op: Op) -> Result<(), Error> {
+        let expected = op.required_inputs();
+        let name = op.name();
+
+        self.execute_op(op).map_err(|e| e.with_op(expected, name))
+    }
+
+    /// Executes the next instruction
+    fn execute_next(&mut self) -> Result<(), Error> {
+        if self.instruction_pointer < self.program.ops().len() {
+            let op = self.program.ops()[self.instruction_pointer].clone();
+            let span = self.program.spans().get(self.instruction_pointer).copied();
+            self.instruction_pointer = self.instruction_pointer.wrapping_add(1);
+
+            self.execute(op).map_err(|e| e.with_span(span.as_ref()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Executes the given op, leaving its signature out of errors.
+    fn execute_op(&mut self, op: Op) -> Result<(), Error> {
         match op {
-            Op::Add => {
-                let a = self.pop_u32()?;
-                let b = self.pop_u32()?;
-                self.push(Data::U32(a.wrapping_add(b)))?;
-                Ok(())
-            }
+            Op::Add => self.arithmetic(|a, b| Some(a + b), |a, b| Some(a.wrapping_add(b))),
             Op::And => {
                 let a = self.pop_bool()?;
                 let b = self.pop_bool()?;
                 self.push(Data::Bool(a && b))?;
                 Ok(())
             }
+            Op::Call(address) => {
+                if self.calls.len() >= MAX_CALL_DEPTH {
+                    return Err(Error::RecursionLimit {
+                        context: Context::default(),
+                        limit: MAX_CALL_DEPTH,
+                    });
+                }
+
+                self.calls.push(self.instruction_pointer);
+                self.instruction_pointer = address;
+                Ok(())
+            }
             Op::Data(data) => {
                 self.push(data)?;
                 Ok(())
@@ -68,21 +243,60 @@ impl PixelMachine {
 
                 Ok(())
             }
-            Op::Divide => {
-                let n = self.pop_u32()?;
-                let divisor = self.pop_u32()?;
+            Op::Divide => self.arithmetic(
+                |n, divisor| (divisor != 0.0).then(|| n / divisor),
+                |n, divisor| n.checked_div(divisor),
+            ),
+            Op::Do => match self.current_block() {
+                Some(Block {
+                    end_idx,
+                    kind: BlockKind::Times,
+                    ..
+                }) => {
+                    let count = self.pop_u32()?;
+                    if count == 0 {
+                        self.instruction_pointer = end_idx + 1;
+                    } else {
+                        self.loops.push(LoopCounter { count, index: 0 });
+                    }
 
-                if divisor == 0 {
-                    return Err(Error::DivideByZero);
+                    Ok(())
                 }
+                Some(Block {
+                    end_idx,
+                    kind: BlockKind::While,
+                    ..
+                }) => {
+                    if !self.pop_bool()? {
+                        self.instruction_pointer = end_idx + 1;
+                    }
 
-                let divided = n / divisor;
+                    Ok(())
+                }
+                Some(Block {
+                    else_idx,
+                    end_idx,
+                    kind: BlockKind::If,
+                    ..
+                }) => {
+                    if !self.pop_bool()? {
+                        self.instruction_pointer = else_idx.unwrap_or(end_idx) + 1;
+                    }
 
-                self.push(Data::U32(divided))?;
-                Ok(())
-            }
-            Op::Do => {
-                // TODO: should this do anything?
+                    Ok(())
+                }
+                // Outside of a compiled program there is no block to jump through.
+                None => Ok(()),
+            },
+            Op::Dot => {
+                let a = self.pop_vector()?;
+                let b = self.pop()?;
+                let dot = match b.components() {
+                    Some(b) if b.len() == a.len() => a.iter().zip(b).map(|(a, b)| a * b).sum(),
+                    _ => return Err(self.invalid_type(b)),
+                };
+
+                self.push(Data::F32(dot))?;
                 Ok(())
             }
             Op::Drop => {
@@ -102,111 +316,122 @@ impl PixelMachine {
                 if a == b {
                     self.push(Data::Bool(true))?;
                 } else {
-                    // If there are two numbers, convert to u32 and try out those
-                    self.push(a)?;
+                    // If there are two numbers, compare them as numbers
                     self.push(b)?;
+                    self.push(a)?;
 
-                    let a = self.pop_u32();
-                    let b = self.pop_u32();
-                    if let Ok(a) = a {
-                        if let Ok(b) = b {
-                            if a == b {
-                                self.push(Data::Bool(true))?;
-                                return Ok(());
-                            }
-                        }
-                    }
+                    let equal = match self.pop_numbers() {
+                        Ok(Numbers::F32(a, b)) => a == b,
+                        Ok(Numbers::U32(a, b)) => a == b,
+                        Err(_) => false,
+                    };
 
-                    self.push(Data::Bool(false))?;
+                    self.push(Data::Bool(equal))?;
                 }
                 Ok(())
             }
-            Op::End => {
-                // This doesn't really do anything as it's mainly a label
+            Op::Else => {
+                // Reaching an `else` means the `if` block was executed, so skip the `else` block
+                if let Some(Block { end_idx, .. }) = self.current_block() {
+                    self.instruction_pointer = end_idx + 1;
+                }
+
                 Ok(())
             }
+            Op::End => match self.current_block() {
+                Some(Block {
+                    do_idx,
+                    kind: BlockKind::Times,
+                    ..
+                }) => {
+                    if let Some(counter) = self.loops.last_mut() {
+                        counter.index += 1;
+                        if counter.index < counter.count {
+                            self.iterate()?;
+                            self.instruction_pointer = do_idx + 1;
+                        } else {
+                            self.loops.pop();
+                        }
+                    }
+
+                    Ok(())
+                }
+                Some(Block {
+                    kind: BlockKind::While,
+                    start_idx,
+                    ..
+                }) => {
+                    self.iterate()?;
+                    self.instruction_pointer = start_idx;
+                    Ok(())
+                }
+                // This doesn't really do anything for an `if` as it's mainly a label
+                _ => Ok(()),
+            },
             Op::FragPos => {
                 self.push(Data::U32(self.x))?;
                 self.push(Data::U32(self.y))?;
                 Ok(())
             }
+            Op::Frame => {
+                self.push(Data::U32(self.frame))?;
+                Ok(())
+            }
             Op::GreaterThan => {
-                let a = self.pop_u32()?;
-                let b = self.pop_u32()?;
-                self.push(Data::Bool(b > a))?;
+                let result = match self.pop_numbers()? {
+                    Numbers::F32(a, b) => b > a,
+                    Numbers::U32(a, b) => b > a,
+                };
+                self.push(Data::Bool(result))?;
                 Ok(())
             }
             Op::GreaterThanEqual => {
-                let a = self.pop_u32()?;
-                let b = self.pop_u32()?;
-                self.push(Data::Bool(b >= a))?;
+                let result = match self.pop_numbers()? {
+                    Numbers::F32(a, b) => b >= a,
+                    Numbers::U32(a, b) => b >= a,
+                };
+                self.push(Data::Bool(result))?;
                 Ok(())
             }
             Op::If => {
-                // Execute conditional
-                let mut found_do = false;
-                while self.peek_next().is_some() {
-                    self.execute_next()?;
-
-                    if let Some(Op::Do) = self.peek_next() {
-                        found_do = true;
-                        break;
-                    }
-                }
-
-                if !found_do {
-                    todo!("Handle 'do' not found!");
-                }
-
-                let should_execute = self.pop_bool()?;
-
-                // Execute do or skip to end
-                loop {
-                    match self.peek_next() {
-                        Some(op) => {
-                            if op == &Op::End {
-                                return Ok(());
-                            }
-                            // Otherwise keep executing
-                        }
-                        None => {
-                            todo!("Required an `execute` op!");
-                        }
-                    };
-
-                    if should_execute == true {
-                        self.execute_next()?;
-                    } else {
-                        // Skip to next
-                        self.instruction_pointer += 1;
-                    }
+                // The condition is popped once `do` is hit
+                Ok(())
+            }
+            Op::Index => match self.loops.last() {
+                Some(counter) => {
+                    let index = counter.index;
+                    self.push(Data::U32(index))?;
+                    Ok(())
                 }
+                None => Err(Error::NotInLoop {
+                    context: Context::default(),
+                }),
+            },
+            Op::Length => {
+                let v = self.pop_vector()?;
+                self.push(Data::F32(length(&v)))?;
+                Ok(())
             }
             Op::LessThan => {
-                let a = self.pop_u32()?;
-                let b = self.pop_u32()?;
-                self.push(Data::Bool(b < a))?;
+                let result = match self.pop_numbers()? {
+                    Numbers::F32(a, b) => b < a,
+                    Numbers::U32(a, b) => b < a,
+                };
+                self.push(Data::Bool(result))?;
                 Ok(())
             }
             Op::LessThanEqual => {
-                let a = self.pop_u32()?;
-                let b = self.pop_u32()?;
-                self.push(Data::Bool(b <= a))?;
-                Ok(())
-            }
-            Op::Modulo => {
-                let n = self.pop_u32()?;
-                let modulus = self.pop_u32()?;
-
-                if modulus == 0 {
-                    return Err(Error::DivideByZero);
-                }
-
-                let modded = n % modulus;
-
-                self.push(Data::U32(modded))?;
+                let result = match self.pop_numbers()? {
+                    Numbers::F32(a, b) => b <= a,
+                    Numbers::U32(a, b) => b <= a,
+                };
+                self.push(Data::Bool(result))?;
                 Ok(())
             }
+            Op::Modulo => self.arithmetic(
+                |n, modulus| (modulus != 0.0).then(|| n % modulus),
+                |n, modulus| n.checked_rem(modulus),
+            ),
             Op::MakeColor => {
                 let a = self.pop_u8()?;
                 let b = self.pop_u8()?;
@@ -217,11 +442,30 @@ impl PixelMachine {
                 self.push(Data::Color(color))?;
                 Ok(())
             }
-            Op::Multiply => {
-                let multiplier = self.pop_u32()?;
-                let n = self.pop_u32()?;
+            Op::Multiply => self.arithmetic(
+                |multiplier, n| Some(n * multiplier),
+                |multiplier, n| Some(n.wrapping_mul(multiplier)),
+            ),
+            Op::Normalize => {
+                let v = self.pop_vector()?;
+                let length = length(&v);
+
+                // A zero vector has no direction, so it is left as is.
+                let normalized: Vec<f32> = if length == 0.0 {
+                    v
+                } else {
+                    v.iter().map(|c| c / length).collect()
+                };
 
-                self.push(Data::U32(n.wrapping_mul(multiplier)))?;
+                self.push_vector(&normalized)?;
+                Ok(())
+            }
+            Op::Return => {
+                // Returning from outside of a word ends the program
+                self.instruction_pointer = match self.calls.pop() {
+                    Some(address) => address,
+                    None => self.program.ops().len(),
+                };
                 Ok(())
             }
             Op::Rot => {
@@ -256,6 +500,19 @@ impl PixelMachine {
                 self.push(b)?;
                 Ok(())
             }
+            Op::Sample { address, filter } => {
+                let texture_id = self.pop_u32()?;
+                let uv = match self.pop()? {
+                    Data::Vec2(uv) => uv,
+                    data => return Err(self.invalid_type(data)),
+                };
+
+                let texture = self.texture(texture_id)?;
+                let color = texture.sample(address.unwrap_or(texture.address), filter, uv);
+
+                self.push(Data::Color(color))?;
+                Ok(())
+            }
             Op::SplitColor => {
                 let color = self.pop_color()?;
                 self.push(Data::U8(color.r))?;
@@ -265,111 +522,218 @@ impl PixelMachine {
 
                 Ok(())
             }
-            Op::Subtract => {
-                let n = self.pop_u32()?;
-                let subtractor = self.pop_u32()?;
-                self.push(Data::U32(n.wrapping_sub(subtractor)))?;
+            Op::SplitVec => {
+                for c in self.pop_vector()? {
+                    self.push(Data::F32(c))?;
+                }
+
+                Ok(())
+            }
+            Op::Subtract => self.arithmetic(
+                |n, subtractor| Some(n - subtractor),
+                |n, subtractor| Some(n.wrapping_sub(subtractor)),
+            ),
+            Op::Swizzle(components) => {
+                let data = self.pop()?;
+                let swizzled = match data.components() {
+                    Some(v) if components.iter().all(|c| *c < v.len()) => {
+                        components.iter().map(|c| v[*c]).collect::<Vec<f32>>()
+                    }
+                    _ => return Err(self.invalid_type(data)),
+                };
+
+                self.push_vector(&swizzled)?;
                 Ok(())
             }
-            // TODO: test
+            Op::Texture(name) => Err(Error::UndefinedTexture {
+                context: Context::default(),
+                name,
+            }),
+            Op::TextureIndex => {
+                let name = self.pop_string()?;
+                let index = self
+                    .textures
+                    .iter()
+                    .position(|texture| texture.name.as_ref() == Some(&name));
+
+                match index {
+                    Some(index) => self.push(Data::U32(index as u32)),
+                    None => Err(Error::UndefinedTexture {
+                        context: Context::default(),
+                        name,
+                    }),
+                }
+            }
             Op::TexturePixel => {
                 let texture_id = self.pop_u32()?;
                 let y = self.pop_u32()?;
                 let x = self.pop_u32()?;
 
-                let color = self.get_color(texture_id, x, y);
+                let texture = self.texture(texture_id)?;
+                let color = texture.pixel(texture.address, x as i64, y as i64);
+
                 self.push(Data::Color(color))?;
                 Ok(())
             }
-        }
-    }
+            Op::Time => {
+                self.push(Data::F32(self.time))?;
+                Ok(())
+            }
+            Op::Times => {
+                // The count is popped once `do` is hit
+                Ok(())
+            }
+            Op::ToColor => {
+                let v = self.pop()?;
+                let color = match v {
+                    Data::Vec4(v) => {
+                        let [r, g, b, a] = v.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+                        Color { r, g, b, a }
+                    }
+                    data => return Err(self.invalid_type(data)),
+                };
 
-    /// Executes the next instruction
-    fn execute_next(&mut self) -> Result<(), Error> {
-        if self.instruction_pointer < self.instructions.len() {
-            let op = self.instructions[self.instruction_pointer].clone();
-            self.instruction_pointer = self.instruction_pointer.wrapping_add(1);
+                self.push(Data::Color(color))?;
+                Ok(())
+            }
+            Op::ToF32 => {
+                let f = self.pop_f32()?;
+                self.push(Data::F32(f))?;
+                Ok(())
+            }
+            Op::ToU32 => {
+                let u = match self.pop()? {
+                    // Float to int casts truncate and saturate
+                    Data::F32(f) => f as u32,
+                    Data::U32(u) => u,
+                    Data::U8(u) => u as u32,
+                    data => return Err(self.invalid_type(data)),
+                };
+                self.push(Data::U32(u))?;
+                Ok(())
+            }
+            Op::ToU8 => {
+                let u = match self.pop()? {
+                    Data::F32(f) => f as u8,
+                    Data::U32(u) => u.min(u8::MAX as u32) as u8,
+                    Data::U8(u) => u,
+                    data => return Err(self.invalid_type(data)),
+                };
+                self.push(Data::U8(u))?;
+                Ok(())
+            }
+            Op::ToVec4 => {
+                let Color { r, g, b, a } = self.pop_color()?;
+                let v = [r, g, b, a].map(|c| c as f32 / 255.0);
 
-            self.execute(op)
-        } else {
-            Ok(())
-        }
-    }
+                self.push(Data::Vec4(v))?;
+                Ok(())
+            }
+            Op::Uniform(name) => Err(Error::UndefinedUniform {
+                context: Context::default(),
+                name,
+            }),
+            Op::UniformValue => {
+                let name = self.pop_string()?;
+                match self.program.uniform(&name) {
+                    Some(data) => self.push(data.clone()),
+                    None => Err(Error::UndefinedUniform {
+                        context: Context::default(),
+                        name,
+                    }),
+                }
+            }
+            Op::Vec2 | Op::Vec3 | Op::Vec4 => {
+                let mut v = vec![0.0; op.required_inputs().len()];
+                for c in v.iter_mut().rev() {
+                    *c = self.pop_f32()?;
+                }
 
-    /// TODO: test
-    fn get_color(&self, texture_id: u32, x: u32, y: u32) -> Color {
-        if self.textures.is_empty() {
-            return Color {
-                r: 255,
-                g: 255,
-                b: 255,
-                a: 255,
-            };
+                self.push_vector(&v)?;
+                Ok(())
+            }
+            Op::While => {
+                // The condition is popped once `do` is hit
+                Ok(())
+            }
         }
+    }
 
-        let texture_id = texture_id as usize;
-        let texture = texture_id % self.textures.len();
-
-        let x = x % self.width;
-        let y = y % self.height;
+    /// Executes every op of the given program, keeping the values already on the stack.
+    pub fn execute_program(&mut self, program: &Program) -> Result<(), Error> {
+        self.execute_steps(program, None)
+    }
 
-        let [r, g, b, a] = self.textures[texture].get_pixel(x, y).0;
+    /// Executes every op of the given program, calling `step` after every executed op when tracing.
+    fn execute_steps(
+        &mut self,
+        program: &Program,
+        mut step: Option<&mut dyn FnMut(Step)>,
+    ) -> Result<(), Error> {
+        self.program = program.clone();
+        self.calls.clear();
+        self.instruction_pointer = self.program.entry();
+        self.iterations = 0;
+        self.loops.clear();
+
+        while self.instruction_pointer < self.program.ops().len() {
+            let instruction_pointer = self.instruction_pointer;
+            self.execute_next()?;
+
+            if let Some(step) = step.as_mut() {
+                step(Step {
+                    instruction_pointer,
+                    op: self.program.ops()[instruction_pointer].clone(),
+                    span: self.program.spans().get(instruction_pointer).copied(),
+                    stack: self.stack_values(),
+                });
+            }
+        }
 
-        Color { r, g, b, a }
+        Ok(())
     }
 
-    /// TODO: tests
-    pub fn interpret(&mut self, program: &str) -> Result<Color, Error> {
-        // Replace all comments
-        let program = {
-            const COMMENT_END: &'static str = "\n";
-            const COMMENT_START: &'static str = "#";
-
-            let mut s = program.to_string().replace("\r\n", "\n");
-
-            let mut i = 0;
-            let mut removing_comment = false;
-            while i < s.len() + 1 {
-                if s.get(i..i + 1).unwrap_or_default() == COMMENT_START {
-                    removing_comment = true;
-                } else if s.get(i..i + 1).unwrap_or_default() == COMMENT_END {
-                    removing_comment = false;
-                }
-
-                if removing_comment {
-                    s.remove(i);
-                } else {
-                    i += 1;
-                }
-            }
-            s
-        };
+    /// Compiles and runs the given source, returning the final color.
+    pub fn interpret(&mut self, source: &str) -> Result<Color, Error> {
+        let program = Program::compile(source)?;
+        self.run(&program)
+    }
 
-        // Parse the program
-        for token in program.split_whitespace() {
-            let op = self.parse(token)?;
-            self.instructions.push(op.clone());
+    /// Returns an invalid type error for the given data.
+    /// The op it occurred on is filled in by `execute`.
+    fn invalid_type(&self, data: Data) -> Error {
+        Error::InvalidType {
+            context: Context::default(),
+            got: data,
         }
+    }
 
-        // Interpret
-        while self.instruction_pointer < self.instructions.len() {
-            let op = self.instructions[self.instruction_pointer].clone();
-            self.instruction_pointer = self.instruction_pointer.wrapping_add(1);
-            self.execute(op)?;
+    /// Counts a loop iteration, erroring if the budget has been exceeded.
+    fn iterate(&mut self) -> Result<(), Error> {
+        self.iterations += 1;
+        if self.iterations > MAX_ITERATIONS {
+            return Err(Error::IterationLimit {
+                context: Context::default(),
+                limit: MAX_ITERATIONS,
+            });
         }
 
-        // Return the final color
-        self.pop_color()
+        Ok(())
     }
 
     /// Creates a new pixel machine.
-    pub fn new(x: u32, y: u32, width: u32, height: u32, textures: Vec<Texture>) -> Self {
+    pub fn new(x: u32, y: u32, width: u32, height: u32, textures: Vec<Arc<Texture>>) -> Self {
         let stack = Stack::new();
         Self {
+            calls: vec![],
+            frame: 0,
             instruction_pointer: 0,
-            instructions: vec![],
+            iterations: 0,
+            loops: vec![],
+            program: Program::default(),
             stack,
             textures,
+            time: 0.0,
             width,
             height,
             x,
@@ -378,40 +742,81 @@ impl PixelMachine {
     }
 
     /// Attempts to parse the given token.
-    pub fn parse(&self, token: &str) -> Result<Op, Error> {
+    pub fn parse(token: &str) -> Result<Op, Error> {
         match token {
             "+" => Ok(Op::Add),
             "&&" => Ok(Op::And),
             "dim" => Ok(Op::Dimensions),
             "/" => Ok(Op::Divide),
             "do" => Ok(Op::Do),
+            "dot" => Ok(Op::Dot),
             "drop" => Ok(Op::Drop),
             "dup" => Ok(Op::Dup),
+            "else" => Ok(Op::Else),
             "end" => Ok(Op::End),
             "==" => Ok(Op::Equal),
             "fragPos" => Ok(Op::FragPos),
+            "frame" => Ok(Op::Frame),
             ">" => Ok(Op::GreaterThan),
             ">=" => Ok(Op::GreaterThanEqual),
             "if" => Ok(Op::If),
+            "index" => Ok(Op::Index),
+            "length" => Ok(Op::Length),
             "<" => Ok(Op::LessThan),
             "<=" => Ok(Op::LessThanEqual),
             "makeColor" => Ok(Op::MakeColor),
             "%" => Ok(Op::Modulo),
             "*" => Ok(Op::Multiply),
+            "normalize" => Ok(Op::Normalize),
             "rot" => Ok(Op::Rot),
             "rotN" => Ok(Op::RotN),
             "splitColor" => Ok(Op::SplitColor),
+            "splitVec" => Ok(Op::SplitVec),
             "-" => Ok(Op::Subtract),
+            "texture" => Ok(Op::TextureIndex),
             "texturePixel" => Ok(Op::TexturePixel),
+            "time" => Ok(Op::Time),
+            "times" => Ok(Op::Times),
+            "toColor" => Ok(Op::ToColor),
+            "toF32" => Ok(Op::ToF32),
+            "toU32" => Ok(Op::ToU32),
+            "toU8" => Ok(Op::ToU8),
+            "toVec4" => Ok(Op::ToVec4),
+            "uniform" => Ok(Op::UniformValue),
+            "vec2" => Ok(Op::Vec2),
+            "vec3" => Ok(Op::Vec3),
+            "vec4" => Ok(Op::Vec4),
+            "while" => Ok(Op::While),
             _ => {
-                if let Ok(u) = token.parse::<u8>() {
-                    Ok(Op::Data(Data::U8(u)))
-                } else if let Ok(u) = token.parse::<u32>() {
-                    Ok(Op::Data(Data::U32(u)))
-                } else if let Ok(b) = token.parse::<bool>() {
-                    Ok(Op::Data(Data::Bool(b)))
+                if token.starts_with('"') {
+                    match parse_string(token) {
+                        Ok(string) => Ok(Op::Data(Data::String(string))),
+                        Err(reason) => Err(Error::InvalidString {
+                            context: Context {
+                                expected: vec![],
+                                op: token.to_string(),
+                                span: None,
+                            },
+                            reason,
+                        }),
+                    }
+                } else if let Some(data) = Data::parse(token) {
+                    Ok(Op::Data(data))
+                } else if let Some(name) = token.strip_prefix('$').filter(|name| !name.is_empty()) {
+                    Ok(Op::Uniform(name.into()))
+                } else if let Some(name) = token.strip_prefix('@').filter(|name| !name.is_empty()) {
+                    Ok(Op::Texture(name.into()))
+                } else if let Some(op) = parse_sample(token) {
+                    Ok(op)
+                } else if let Some(components) = parse_swizzle(token) {
+                    Ok(Op::Swizzle(components))
                 } else {
                     Err(Error::UnhandledToken {
+                        context: Context {
+                            expected: vec![],
+                            op: token.to_string(),
+                            span: None,
+                        },
                         got: token.to_string(),
                     })
                 }
@@ -419,56 +824,91 @@ impl PixelMachine {
         }
     }
 
-    /// Peeks the next instruction
-    fn peek_next(&self) -> Option<&Op> {
-        if self.instruction_pointer < self.instructions.len() {
-            Some(&self.instructions[self.instruction_pointer])
-        } else {
-            None
-        }
-    }
-
     /// Pops a value off the stack.
     fn pop(&mut self) -> Result<Data, Error> {
-        // TODO: wire up required types in the event of an error.
-
         match self.stack.pop() {
             Some(data) => Ok(data),
-            None => Err(Error::StackUnderflow),
+            None => Err(Error::StackUnderflow {
+                context: Context::default(),
+            }),
         }
     }
 
     /// Pops a bool off the stack.
     fn pop_bool(&mut self) -> Result<bool, Error> {
-        // TODO: wire up required types in the event of an error.
-
         match self.pop()? {
             Data::Bool(value) => Ok(value),
             data => Err(self.invalid_type(data)),
         }
     }
 
-    fn invalid_type(&self, data: Data) -> Error {
-        Error::InvalidType {
-            got: data,
-            instruction_pointer: self.instruction_pointer,
-        }
-    }
-
     /// Pops a color off the stack.
     fn pop_color(&mut self) -> Result<Color, Error> {
-        // TODO: wire up required types in the event of an error.
-
         match self.pop()? {
             Data::Color(value) => Ok(value),
             data => Err(self.invalid_type(data)),
         }
     }
 
+    /// Pops a number off the stack as a float.
+    fn pop_f32(&mut self) -> Result<f32, Error> {
+        match self.pop()? {
+            Data::F32(f) => Ok(f),
+            Data::U32(u) => Ok(u as f32),
+            Data::U8(u) => Ok(u as f32),
+            data => Err(self.invalid_type(data)),
+        }
+    }
+
+    /// Pops the top two numbers off the stack, with the top first.
+    fn pop_numbers(&mut self) -> Result<Numbers, Error> {
+        let a = self.pop()?;
+        let b = self.pop()?;
+
+        match (a, b) {
+            (Data::F32(a), b) => {
+                self.push(b)?;
+                Ok(Numbers::F32(a, self.pop_f32()?))
+            }
+            (a, Data::F32(b)) => {
+                self.push(a)?;
+                Ok(Numbers::F32(self.pop_f32()?, b))
+            }
+            (a, b) => {
+                self.push(b)?;
+                let b = self.pop_u32();
+                self.push(a)?;
+                let a = self.pop_u32()?;
+
+                Ok(Numbers::U32(a, b?))
+            }
+        }
+    }
+
+    /// Pops a color for each output off the stack, returning them in the order they were pushed.
+    fn pop_outputs(&mut self, outputs: usize) -> Result<Vec<Color>, Error> {
+        // The final op is what left the result on the stack.
+        let last_op = self
+            .program
+            .ops()
+            .last()
+            .map(|op| op.name())
+            .unwrap_or_default();
+        let mut colors = Vec::with_capacity(outputs);
+        for _ in 0..outputs {
+            let color = self.pop_color().map_err(|e| {
+                e.with_op(&vec![Type::Color; outputs], last_op)
+                    .with_span(self.program.spans().last())
+            })?;
+            colors.push(color);
+        }
+
+        colors.reverse();
+        Ok(colors)
+    }
+
     /// Pops a string off the stack.
     fn pop_string(&mut self) -> Result<String, Error> {
-        // TODO: wire up required types in the event of an error.
-
         match self.pop()? {
             Data::String(value) => Ok(value),
             data => Err(self.invalid_type(data)),
@@ -477,8 +917,6 @@ impl PixelMachine {
 
     /// Pops a u32 off the stack
     fn pop_u32(&mut self) -> Result<u32, Error> {
-        // TODO: wire up required types in the event of an error.
-
         match self.pop()? {
             Data::U8(u) => Ok(u as u32),
             Data::U32(u) => Ok(u),
@@ -488,8 +926,6 @@ impl PixelMachine {
 
     /// Pops a u8 off the stack
     fn pop_u8(&mut self) -> Result<u8, Error> {
-        // TODO: wire up required types in the event of an error.
-
         match self.pop()? {
             Data::U32(u) => {
                 let u = {
@@ -507,37 +943,142 @@ impl PixelMachine {
         }
     }
 
+    /// Pops a vector off the stack.
+    fn pop_vector(&mut self) -> Result<Vec<f32>, Error> {
+        let data = self.pop()?;
+        match data.components() {
+            Some(v) => Ok(v.to_vec()),
+            None => Err(self.invalid_type(data)),
+        }
+    }
+
     /// Pushes a value onto the stack.
     pub fn push(&mut self, data: Data) -> Result<(), Error> {
         self.stack.push(data);
         Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    const W: u32 = 640;
-    const H: u32 = 480;
 
-    fn machine() -> PixelMachine {
-        PixelMachine::new(
-            320,
-            240,
-            W,
-            H,
-            vec![std::sync::Arc::new(image::DynamicImage::new_rgba8(W, H))],
-        )
+    /// Pushes a float or vector with the given components onto the stack.
+    fn push_vector(&mut self, components: &[f32]) -> Result<(), Error> {
+        match Data::from_components(components) {
+            Some(data) => self.push(data),
+            None => Ok(()),
+        }
     }
 
-    #[test]
-    fn peek_next() {
-        todo!("do tests!");
+    /// Runs the given program, returning the final color.
+    pub fn run(&mut self, program: &Program) -> Result<Color, Error> {
+        let mut colors = self.run_outputs(1, program)?;
+        Ok(colors.remove(0))
     }
 
-    #[test]
-    fn execute_next() {
-        todo!("do tests!");
+    /// Runs the given program, returning a color for each output.
+    /// The colors are returned in the order they were pushed.
+    pub fn run_outputs(&mut self, outputs: usize, program: &Program) -> Result<Vec<Color>, Error> {
+        self.execute_steps(program, None)?;
+        self.pop_outputs(outputs)
+    }
+
+    /// Returns the values on the stack from the bottom to the top, leaving the stack unchanged.
+    pub fn stack_values(&mut self) -> Vec<Data> {
+        let mut values = vec![];
+        while let Some(data) = self.stack.pop() {
+            values.push(data);
+        }
+
+        values.reverse();
+        for data in &values {
+            self.stack.push(data.clone());
+        }
+
+        values
+    }
+
+    /// Returns the texture for the given index.
+    fn texture(&self, texture_id: u32) -> Result<&Texture, Error> {
+        match self.textures.get(texture_id as usize) {
+            Some(texture) => Ok(texture),
+            None => Err(Error::InvalidTexture {
+                context: Context::default(),
+                index: texture_id,
+                len: self.textures.len(),
+            }),
+        }
+    }
+
+    /// Runs the given program like `run_outputs`, calling `step` after every executed op.
+    pub fn trace(
+        &mut self,
+        outputs: usize,
+        program: &Program,
+        mut step: impl FnMut(Step),
+    ) -> Result<Vec<Color>, Error> {
+        self.execute_steps(program, Some(&mut step))?;
+        self.pop_outputs(outputs)
+    }
+
+    /// Sets the index and elapsed time in seconds of the frame being rendered.
+    pub fn with_frame(mut self, frame: u32, time: f32) -> Self {
+        self.frame = frame;
+        self.time = time;
+        self
+    }
+}
+
+/// An op executed while tracing a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// The index of the op in the program.
+    pub instruction_pointer: usize,
+    pub op: Op,
+    /// Where the op is in the source, if compiled from one.
+    pub span: Option<Span>,
+    /// The values on the stack after the op executed, from the bottom to the top.
+    pub stack: Vec<Data>,
+}
+
+/// The counter of a `times` loop.
+#[derive(Debug, Clone, PartialEq)]
+struct LoopCounter {
+    count: u32,
+    index: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const W: u32 = 640;
+    const H: u32 = 480;
+
+    fn machine() -> PixelMachine {
+        PixelMachine::new(
+            320,
+            240,
+            W,
+            H,
+            vec![Arc::new(Texture::new(image::DynamicImage::new_rgba8(W, H)))],
+        )
+    }
+
+    #[test]
+    fn execute_next() {
+        let mut m = machine();
+        m.program = Program::compile("1 2 +").unwrap();
+        for _ in 0..3 {
+            m.execute_next().unwrap();
+        }
+        assert_eq!(3, m.instruction_pointer);
+        assert_eq!(vec![Data::U32(3)], m.stack_values());
+
+        // Past the last op nothing is executed.
+        m.execute_next().unwrap();
+        assert_eq!(3, m.instruction_pointer);
+        assert_eq!(vec![Data::U32(3)], m.stack_values());
+
+        m.program = Program::compile("1 true +").unwrap();
+        m.instruction_pointer = 0;
+        let e = (0..3).try_for_each(|_| m.execute_next()).unwrap_err();
+        assert_eq!(Some(8), e.context().span.map(|span| span.column));
     }
 
     mod execute {
@@ -553,6 +1094,44 @@ mod tests {
             assert_eq!(Ok(4), m.pop_u32());
         }
 
+        #[test]
+        fn add_floats() {
+            let mut m = machine();
+            m.push(Data::U32(2)).unwrap();
+            m.push(Data::F32(0.5)).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Add));
+
+            assert_eq!(Ok(Data::F32(2.5)), m.pop());
+        }
+
+        #[test]
+        fn add_vectors() {
+            let mut m = machine();
+            m.push(Data::Vec2([1.0, 2.0])).unwrap();
+            m.push(Data::Vec2([0.5, 0.5])).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Add));
+            assert_eq!(Ok(Data::Vec2([1.5, 2.5])), m.pop());
+
+            m.push(Data::U8(1)).unwrap();
+            m.push(Data::Vec3([1.0, 2.0, 3.0])).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Add));
+            assert_eq!(Ok(Data::Vec3([2.0, 3.0, 4.0])), m.pop());
+        }
+
+        #[test]
+        fn add_vectors_mismatch() {
+            let mut m = machine();
+            m.push(Data::Vec2([1.0, 2.0])).unwrap();
+            m.push(Data::Vec3([1.0, 2.0, 3.0])).unwrap();
+            assert_eq!(
+                Err(Error::InvalidType {
+                    context: Context::new(&Op::Add, None),
+                    got: Data::Vec2([1.0, 2.0]),
+                }),
+                m.execute(Op::Add)
+            );
+        }
+
         #[test]
         fn add_wraps() {
             let mut m = machine();
@@ -570,8 +1149,8 @@ mod tests {
             m.push(Data::Bool(true)).unwrap();
             assert_eq!(
                 Err(Error::InvalidType {
+                    context: Context::new(&Op::Add, None),
                     got: Data::Bool(true),
-                    instruction_pointer: 0
                 }),
                 m.execute(Op::Add)
             );
@@ -581,8 +1160,8 @@ mod tests {
             m.push(Data::U32(2)).unwrap();
             assert_eq!(
                 Err(Error::InvalidType {
+                    context: Context::new(&Op::Add, None),
                     got: Data::String("garbage".into()),
-                    instruction_pointer: 0
                 }),
                 m.execute(Op::Add)
             );
@@ -621,8 +1200,8 @@ mod tests {
             m.push(Data::U8(0)).unwrap();
             assert_eq!(
                 Err(Error::InvalidType {
-                    instruction_pointer: 0,
-                    got: Data::U8(0)
+                    context: Context::new(&Op::And, None),
+                    got: Data::U8(0),
                 }),
                 m.execute(Op::And)
             );
@@ -631,8 +1210,8 @@ mod tests {
             m.push(Data::Bool(true)).unwrap();
             assert_eq!(
                 Err(Error::InvalidType {
-                    instruction_pointer: 0,
-                    got: Data::U8(0)
+                    context: Context::new(&Op::And, None),
+                    got: Data::U8(0),
                 }),
                 m.execute(Op::And)
             );
@@ -674,7 +1253,48 @@ mod tests {
             let mut m = machine();
             m.push(Data::U32(0)).unwrap();
             m.push(Data::U32(8)).unwrap();
-            assert_eq!(Err(Error::DivideByZero), m.execute(Op::Divide));
+            assert_eq!(
+                Err(Error::DivideByZero {
+                    context: Context::new(&Op::Divide, None)
+                }),
+                m.execute(Op::Divide)
+            );
+        }
+
+        #[test]
+        fn divide_floats() {
+            let mut m = machine();
+            m.push(Data::F32(4.0)).unwrap();
+            m.push(Data::U8(1)).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Divide));
+
+            assert_eq!(Ok(Data::F32(0.25)), m.pop());
+        }
+
+        #[test]
+        fn divide_floats_by_zero() {
+            let mut m = machine();
+            m.push(Data::F32(0.0)).unwrap();
+            m.push(Data::F32(1.0)).unwrap();
+            assert_eq!(
+                Err(Error::DivideByZero {
+                    context: Context::new(&Op::Divide, None)
+                }),
+                m.execute(Op::Divide)
+            );
+        }
+
+        #[test]
+        fn divide_vector_by_zero() {
+            let mut m = machine();
+            m.push(Data::Vec2([1.0, 0.0])).unwrap();
+            m.push(Data::Vec2([1.0, 1.0])).unwrap();
+            assert_eq!(
+                Err(Error::DivideByZero {
+                    context: Context::new(&Op::Divide, None)
+                }),
+                m.execute(Op::Divide)
+            );
         }
 
         #[test]
@@ -684,8 +1304,8 @@ mod tests {
             m.push(Data::Bool(true)).unwrap();
             assert_eq!(
                 Err(Error::InvalidType {
+                    context: Context::new(&Op::Divide, None),
                     got: Data::Bool(true),
-                    instruction_pointer: 0
                 }),
                 m.execute(Op::Divide)
             );
@@ -695,16 +1315,45 @@ mod tests {
             m.push(Data::U32(2)).unwrap();
             assert_eq!(
                 Err(Error::InvalidType {
+                    context: Context::new(&Op::Divide, None),
                     got: Data::String("garbage".into()),
-                    instruction_pointer: 0
                 }),
                 m.execute(Op::Divide)
             );
         }
 
+        #[test]
+        fn dot() {
+            let mut m = machine();
+            m.push(Data::Vec3([1.0, 2.0, 3.0])).unwrap();
+            m.push(Data::Vec3([4.0, 5.0, 6.0])).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Dot));
+
+            assert_eq!(Ok(32.0), m.pop_f32());
+        }
+
+        #[test]
+        fn dot_mismatch() {
+            let mut m = machine();
+            m.push(Data::Vec2([1.0, 2.0])).unwrap();
+            m.push(Data::Vec3([4.0, 5.0, 6.0])).unwrap();
+            assert_eq!(
+                Err(Error::InvalidType {
+                    context: Context::new(&Op::Dot, None),
+                    got: Data::Vec2([1.0, 2.0]),
+                }),
+                m.execute(Op::Dot)
+            );
+        }
+
         #[test]
         fn drop_no_stack_returns_err() {
-            assert_eq!(Err(Error::StackUnderflow), machine().execute(Op::Drop));
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::new(&Op::Drop, None)
+                }),
+                machine().execute(Op::Drop)
+            );
         }
 
         #[test]
@@ -717,7 +1366,12 @@ mod tests {
 
         #[test]
         fn dup_no_stack_returns_err() {
-            assert_eq!(Err(Error::StackUnderflow), machine().execute(Op::Dup));
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::new(&Op::Dup, None)
+                }),
+                machine().execute(Op::Dup)
+            );
         }
 
         #[test]
@@ -781,6 +1435,16 @@ mod tests {
             assert_eq!(false, m.pop_bool().unwrap());
         }
 
+        #[test]
+        fn eq_float_and_int() {
+            let mut m = machine();
+            m.push(Data::F32(2.0)).unwrap();
+            m.push(Data::U8(2)).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Equal));
+
+            assert_eq!(Ok(true), m.pop_bool());
+        }
+
         #[test]
         fn frag_pos() {
             let mut m = machine();
@@ -791,6 +1455,13 @@ mod tests {
             assert_eq!(expected_x, m.pop_u32().unwrap());
         }
 
+        #[test]
+        fn frame() {
+            let mut m = machine().with_frame(3, 0.5);
+            m.execute(Op::Frame).unwrap();
+            assert_eq!(Ok(3), m.pop_u32());
+        }
+
         #[test]
         fn greater_than_true() {
             let mut m = machine();
@@ -944,9 +1615,114 @@ mod tests {
             );
         }
 
+        #[test]
+        fn if_else_executes_else_block() {
+            let result = machine().interpret(
+                "
+            if 1 2 == do
+                4 3 2 1 makeColor
+            else
+                1 2 3 4 makeColor
+            end
+            ",
+            );
+
+            assert_eq!(Ok((1, 2, 3, 4).into()), result);
+        }
+
+        #[test]
+        fn if_else_skips_else_block() {
+            let result = machine().interpret(
+                "
+            if 1 1 == do
+                4 3 2 1 makeColor
+            else
+                1 2 3 4 makeColor
+            end
+            ",
+            );
+
+            assert_eq!(Ok((4, 3, 2, 1).into()), result);
+        }
+
+        #[test]
+        fn if_nested_in_skipped_block() {
+            let result = machine().interpret(
+                "
+            6 6 6 6 makeColor
+            if false do
+                if true do 1 2 3 4 makeColor end
+                5 5 5 5 makeColor
+            end
+            ",
+            );
+
+            assert_eq!(Ok((6, 6, 6, 6).into()), result);
+        }
+
+        #[test]
+        fn if_nested_with_else() {
+            let result = machine().interpret(
+                "
+            if true do
+                if false do 1 else 2 end
+                if true do 3 else 4 end
+            else
+                5 6
+            end
+            0 0 makeColor
+            ",
+            );
+
+            assert_eq!(Ok((2, 3, 0, 0).into()), result);
+        }
+
+        #[test]
+        fn if_no_do() {
+            assert_eq!(
+                Err(Error::UnbalancedBlock {
+                    context: Context::new(
+                        &Op::If,
+                        Some(&Span {
+                            column: 1,
+                            file: 0,
+                            len: 2,
+                            line: 1
+                        })
+                    ),
+                    missing: "do"
+                }),
+                machine().interpret("if true end")
+            );
+        }
+
         #[test]
         fn if_no_end() {
-            todo!()
+            assert_eq!(
+                Err(Error::UnbalancedBlock {
+                    context: Context::new(
+                        &Op::If,
+                        Some(&Span {
+                            column: 1,
+                            file: 0,
+                            len: 2,
+                            line: 1
+                        })
+                    ),
+                    missing: "end"
+                }),
+                machine().interpret("if true do 1 2 3 4 makeColor")
+            );
+        }
+
+        #[test]
+        fn index_outside_loop() {
+            assert_eq!(
+                Err(Error::NotInLoop {
+                    context: Context::new(&Op::Index, None)
+                }),
+                machine().execute(Op::Index)
+            );
         }
 
         #[test]
@@ -971,6 +1747,15 @@ mod tests {
             assert_eq!(true, m.pop_bool().unwrap());
         }
 
+        #[test]
+        fn length() {
+            let mut m = machine();
+            m.push(Data::Vec2([3.0, 4.0])).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Length));
+
+            assert_eq!(Ok(5.0), m.pop_f32());
+        }
+
         #[test]
         fn less_than_false() {
             let mut m = machine();
@@ -993,6 +1778,16 @@ mod tests {
             assert_eq!(false, m.pop_bool().unwrap());
         }
 
+        #[test]
+        fn less_than_floats() {
+            let mut m = machine();
+            m.push(Data::F32(0.5)).unwrap();
+            m.push(Data::U8(1)).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::LessThan));
+
+            assert_eq!(Ok(true), m.pop_bool());
+        }
+
         #[test]
         fn less_than_equal_true() {
             let mut m = machine();
@@ -1083,7 +1878,12 @@ mod tests {
             let mut m = machine();
             m.push(Data::U32(0)).unwrap();
             m.push(Data::U32(8)).unwrap();
-            assert_eq!(Err(Error::DivideByZero), m.execute(Op::Modulo));
+            assert_eq!(
+                Err(Error::DivideByZero {
+                    context: Context::new(&Op::Modulo, None)
+                }),
+                m.execute(Op::Modulo)
+            );
         }
 
         #[test]
@@ -1093,8 +1893,8 @@ mod tests {
             m.push(Data::Bool(true)).unwrap();
             assert_eq!(
                 Err(Error::InvalidType {
+                    context: Context::new(&Op::Divide, None),
                     got: Data::Bool(true),
-                    instruction_pointer: 0
                 }),
                 m.execute(Op::Divide)
             );
@@ -1104,8 +1904,8 @@ mod tests {
             m.push(Data::U32(2)).unwrap();
             assert_eq!(
                 Err(Error::InvalidType {
+                    context: Context::new(&Op::Modulo, None),
                     got: Data::String("garbage".into()),
-                    instruction_pointer: 0
                 }),
                 m.execute(Op::Modulo)
             );
@@ -1131,11 +1931,34 @@ mod tests {
             assert_eq!(Ok(u32::MAX.wrapping_mul(3)), m.pop_u32());
         }
 
+        #[test]
+        fn normalize() {
+            let mut m = machine();
+            m.push(Data::Vec2([3.0, 4.0])).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Normalize));
+
+            assert_eq!(Ok(Data::Vec2([0.6, 0.8])), m.pop());
+        }
+
+        #[test]
+        fn normalize_zero() {
+            let mut m = machine();
+            m.push(Data::Vec2([0.0, 0.0])).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Normalize));
+
+            assert_eq!(Ok(Data::Vec2([0.0, 0.0])), m.pop());
+        }
+
         #[test]
         fn rot_0_element_underflows() {
             let mut m = machine();
 
-            assert_eq!(Err(Error::StackUnderflow), m.execute(Op::Rot));
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::new(&Op::Rot, None)
+                }),
+                m.execute(Op::Rot)
+            );
         }
 
         #[test]
@@ -1143,7 +1966,12 @@ mod tests {
             let mut m = machine();
             m.push(Data::Bool(true)).unwrap();
 
-            assert_eq!(Err(Error::StackUnderflow), m.execute(Op::Rot));
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::new(&Op::Rot, None)
+                }),
+                m.execute(Op::Rot)
+            );
         }
 
         #[test]
@@ -1182,66 +2010,454 @@ mod tests {
             assert_eq!(true, m.pop_bool().unwrap());
         }
 
+        #[test]
+        fn sample() {
+            let op = Op::Sample {
+                address: None,
+                filter: Filter::Bilinear,
+            };
+            let mut m = machine();
+            m.push(Data::Vec2([0.5, 0.5])).unwrap();
+            m.push(Data::U8(0)).unwrap();
+            assert_eq!(Ok(()), m.execute(op));
+
+            assert_eq!(Ok((0, 0, 0, 0).into()), m.pop_color());
+        }
+
+        #[test]
+        fn sample_address_overrides_texture() {
+            let texture = Texture {
+                address: Address::Border,
+                border: (1, 2, 3, 4).into(),
+                ..Texture::new(image::DynamicImage::new_rgba8(W, H))
+            };
+            let mut m = PixelMachine::new(0, 0, W, H, vec![Arc::new(texture)]);
+
+            let op = |address| Op::Sample {
+                address,
+                filter: Filter::Nearest,
+            };
+
+            m.push(Data::Vec2([-0.5, 0.5])).unwrap();
+            m.push(Data::U8(0)).unwrap();
+            assert_eq!(Ok(()), m.execute(op(None)));
+            assert_eq!(Ok((1, 2, 3, 4).into()), m.pop_color());
+
+            m.push(Data::Vec2([-0.5, 0.5])).unwrap();
+            m.push(Data::U8(0)).unwrap();
+            assert_eq!(Ok(()), m.execute(op(Some(Address::Repeat))));
+            assert_eq!(Ok((0, 0, 0, 0).into()), m.pop_color());
+        }
+
+        #[test]
+        fn sample_no_textures() {
+            let op = Op::Sample {
+                address: None,
+                filter: Filter::Nearest,
+            };
+            let mut m = PixelMachine::new(0, 0, W, H, vec![]);
+            m.push(Data::Vec2([0.5, 0.5])).unwrap();
+            m.push(Data::U8(0)).unwrap();
+            assert_eq!(
+                Err(Error::InvalidTexture {
+                    context: Context::new(&op, None),
+                    index: 0,
+                    len: 0,
+                }),
+                m.execute(op)
+            );
+        }
+
+        #[test]
+        fn sample_not_vec2() {
+            let op = Op::Sample {
+                address: None,
+                filter: Filter::Bicubic,
+            };
+            let mut m = machine();
+            m.push(Data::U8(1)).unwrap();
+            m.push(Data::U8(0)).unwrap();
+            assert_eq!(
+                Err(Error::InvalidType {
+                    context: Context::new(&op, None),
+                    got: Data::U8(1),
+                }),
+                m.execute(op)
+            );
+        }
+
         #[test]
         fn split_color_underflow() {
             let mut m = machine();
 
-            assert_eq!(Err(Error::StackUnderflow), m.execute(Op::SplitColor));
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::new(&Op::SplitColor, None)
+                }),
+                m.execute(Op::SplitColor)
+            );
+        }
+
+        #[test]
+        fn split_color() {
+            let mut m = machine();
+            m.push(Data::Color((0, 1, 2, 3).into())).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::SplitColor));
+            assert_eq!(3, m.pop_u8().unwrap());
+            assert_eq!(2, m.pop_u8().unwrap());
+            assert_eq!(1, m.pop_u8().unwrap());
+            assert_eq!(0, m.pop_u8().unwrap());
+        }
+
+        #[test]
+        fn split_vec() {
+            let mut m = machine();
+            m.push(Data::Vec3([1.0, 2.0, 3.0])).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::SplitVec));
+
+            assert_eq!(Ok(3.0), m.pop_f32());
+            assert_eq!(Ok(2.0), m.pop_f32());
+            assert_eq!(Ok(1.0), m.pop_f32());
+        }
+
+        #[test]
+        fn subtract() {
+            let mut m = machine();
+            m.push(Data::U32(7)).unwrap();
+            m.push(Data::U32(9)).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Subtract));
+
+            assert_eq!(Ok(2), m.pop_u32());
+        }
+
+        #[test]
+        fn sub_wraps() {
+            let mut m = machine();
+            m.push(Data::U32(9)).unwrap();
+            m.push(Data::U32(2)).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Subtract));
+            assert_eq!(Ok((2 as u32).wrapping_sub(9)), m.pop_u32());
+        }
+
+        #[test]
+        fn subtract_not_numbers() {
+            let mut m = machine();
+            m.push(Data::U32(2)).unwrap();
+            m.push(Data::Bool(true)).unwrap();
+            assert_eq!(
+                Err(Error::InvalidType {
+                    context: Context::new(&Op::Divide, None),
+                    got: Data::Bool(true),
+                }),
+                m.execute(Op::Divide)
+            );
+
+            let mut m = machine();
+            m.push(Data::String("garbage".into())).unwrap();
+            m.push(Data::U32(2)).unwrap();
+            assert_eq!(
+                Err(Error::InvalidType {
+                    context: Context::new(&Op::Subtract, None),
+                    got: Data::String("garbage".into()),
+                }),
+                m.execute(Op::Subtract)
+            );
+        }
+
+        #[test]
+        fn swizzle() {
+            let mut m = machine();
+            m.push(Data::Vec4([1.0, 2.0, 3.0, 4.0])).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Swizzle(vec![2, 0, 0])));
+            assert_eq!(Ok(Data::Vec3([3.0, 1.0, 1.0])), m.pop());
+
+            m.push(Data::Vec2([1.0, 2.0])).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Swizzle(vec![1])));
+            assert_eq!(Ok(Data::F32(2.0)), m.pop());
+        }
+
+        #[test]
+        fn swizzle_out_of_range() {
+            let mut m = machine();
+            m.push(Data::Vec2([1.0, 2.0])).unwrap();
+            assert_eq!(
+                Err(Error::InvalidType {
+                    context: Context::new(&Op::Swizzle(vec![2]), None),
+                    got: Data::Vec2([1.0, 2.0]),
+                }),
+                m.execute(Op::Swizzle(vec![2]))
+            );
+        }
+
+        #[test]
+        fn texture_index() {
+            let mut texture = Texture::new(image::DynamicImage::new_rgba8(W, H));
+            texture.name = Some("mummy".into());
+
+            let mut m = machine();
+            m.textures.push(Arc::new(texture));
+            m.push(Data::String("mummy".into())).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::TextureIndex));
+            assert_eq!(Ok(1), m.pop_u32());
+        }
+
+        #[test]
+        fn texture_index_undefined() {
+            let mut m = machine();
+            m.push(Data::String("mummy".into())).unwrap();
+            assert_eq!(
+                Err(Error::UndefinedTexture {
+                    context: Context::new(&Op::TextureIndex, None),
+                    name: "mummy".into(),
+                }),
+                m.execute(Op::TextureIndex)
+            );
+        }
+
+        #[test]
+        fn texture_unbound() {
+            let mut m = machine();
+            assert_eq!(
+                Err(Error::UndefinedTexture {
+                    context: Context::new(&Op::Texture("mummy".into()), None),
+                    name: "mummy".into(),
+                }),
+                m.execute(Op::Texture("mummy".into()))
+            );
+        }
+
+        #[test]
+        fn time() {
+            let mut m = machine().with_frame(3, 0.5);
+            m.execute(Op::Time).unwrap();
+            assert_eq!(Ok(0.5), m.pop_f32());
+        }
+
+        #[test]
+        fn times_executes_block() {
+            let mut m = machine();
+
+            let result = m.interpret(
+                "
+            0
+            times 3 do
+                index +
+                times 2 do 1 + end
+            end
+            dup dup 255 makeColor
+            ",
+            );
+
+            assert_eq!(Ok((9, 9, 9, 255).into()), result);
+        }
+
+        #[test]
+        fn times_zero_skips_block() {
+            let mut m = machine();
+
+            let result = m.interpret(
+                "
+            6 6 6 6 makeColor
+            times 0 do
+                drop
+            end
+            ",
+            );
+
+            assert_eq!(Ok((6, 6, 6, 6).into()), result);
+        }
+
+        #[test]
+        fn to_color() {
+            let mut m = machine();
+            m.push(Data::Vec4([0.0, 0.5, 1.0, 2.0])).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::ToColor));
+
+            assert_eq!(Ok((0, 128, 255, 255).into()), m.pop_color());
+        }
+
+        #[test]
+        fn to_f32() {
+            let mut m = machine();
+            m.push(Data::U32(3)).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::ToF32));
+
+            assert_eq!(Ok(Data::F32(3.0)), m.pop());
+        }
+
+        #[test]
+        fn to_u32_truncates() {
+            let mut m = machine();
+            m.push(Data::F32(2.75)).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::ToU32));
+
+            assert_eq!(Ok(Data::U32(2)), m.pop());
+        }
+
+        #[test]
+        fn to_u8_not_number() {
+            let mut m = machine();
+            m.push(Data::Bool(true)).unwrap();
+            assert_eq!(
+                Err(Error::InvalidType {
+                    context: Context::new(&Op::ToU8, None),
+                    got: Data::Bool(true),
+                }),
+                m.execute(Op::ToU8)
+            );
+        }
+
+        #[test]
+        fn to_u8_saturates() {
+            let mut m = machine();
+            m.push(Data::F32(-1.0)).unwrap();
+            m.push(Data::F32(300.0)).unwrap();
+            m.push(Data::U32(256)).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::ToU8));
+            assert_eq!(Ok(Data::U8(255)), m.pop());
+
+            assert_eq!(Ok(()), m.execute(Op::ToU8));
+            assert_eq!(Ok(Data::U8(255)), m.pop());
+
+            assert_eq!(Ok(()), m.execute(Op::ToU8));
+            assert_eq!(Ok(Data::U8(0)), m.pop());
+        }
+
+        #[test]
+        fn to_vec4() {
+            let mut m = machine();
+            m.push(Data::Color((0, 51, 255, 255).into())).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::ToVec4));
+
+            assert_eq!(Ok(Data::Vec4([0.0, 0.2, 1.0, 1.0])), m.pop());
+        }
+
+        #[test]
+        fn uniform_unbound() {
+            let mut m = machine();
+            assert_eq!(
+                Err(Error::UndefinedUniform {
+                    context: Context::new(&Op::Uniform("threshold".into()), None),
+                    name: "threshold".into(),
+                }),
+                m.execute(Op::Uniform("threshold".into()))
+            );
+        }
+
+        #[test]
+        fn uniform_value() {
+            let mut uniforms = std::collections::HashMap::new();
+            uniforms.insert("threshold".to_string(), Data::F32(0.5));
+
+            let mut m = machine();
+            m.program = Program::default().bind(&[], &uniforms).unwrap();
+            m.push(Data::String("threshold".into())).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::UniformValue));
+            assert_eq!(Ok(Data::F32(0.5)), m.pop());
+
+            m.push(Data::String("gain".into())).unwrap();
+            assert_eq!(
+                Err(Error::UndefinedUniform {
+                    context: Context::new(&Op::UniformValue, None),
+                    name: "gain".into(),
+                }),
+                m.execute(Op::UniformValue)
+            );
+        }
+
+        #[test]
+        fn vec2() {
+            let mut m = machine();
+            m.push(Data::U32(1)).unwrap();
+            m.push(Data::F32(0.5)).unwrap();
+            assert_eq!(Ok(()), m.execute(Op::Vec2));
+
+            assert_eq!(Ok(Data::Vec2([1.0, 0.5])), m.pop());
         }
 
         #[test]
-        fn split_color() {
+        fn vec4_underflows() {
             let mut m = machine();
-            m.push(Data::Color((0, 1, 2, 3).into())).unwrap();
-            assert_eq!(Ok(()), m.execute(Op::SplitColor));
-            assert_eq!(3, m.pop_u8().unwrap());
-            assert_eq!(2, m.pop_u8().unwrap());
-            assert_eq!(1, m.pop_u8().unwrap());
-            assert_eq!(0, m.pop_u8().unwrap());
+            m.push(Data::U32(1)).unwrap();
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::new(&Op::Vec4, None),
+                }),
+                m.execute(Op::Vec4)
+            );
         }
 
         #[test]
-        fn subtract() {
+        fn while_executes_block() {
             let mut m = machine();
-            m.push(Data::U32(7)).unwrap();
-            m.push(Data::U32(9)).unwrap();
-            assert_eq!(Ok(()), m.execute(Op::Subtract));
 
-            assert_eq!(Ok(2), m.pop_u32());
-        }
+            let result = m.interpret(
+                "
+            1
+            while dup 100 < do
+                2 *
+            end
+            dup dup 255 makeColor
+            ",
+            );
 
-        #[test]
-        fn sub_wraps() {
-            let mut m = machine();
-            m.push(Data::U32(9)).unwrap();
-            m.push(Data::U32(2)).unwrap();
-            assert_eq!(Ok(()), m.execute(Op::Subtract));
-            assert_eq!(Ok((2 as u32).wrapping_sub(9)), m.pop_u32());
+            assert_eq!(Ok((128, 128, 128, 255).into()), result);
         }
 
         #[test]
-        fn subtract_not_numbers() {
+        fn while_exceeds_iteration_limit() {
             let mut m = machine();
-            m.push(Data::U32(2)).unwrap();
-            m.push(Data::Bool(true)).unwrap();
+
+            let result = m.interpret("while true do end");
+
             assert_eq!(
-                Err(Error::InvalidType {
-                    got: Data::Bool(true),
-                    instruction_pointer: 0
+                Err(Error::IterationLimit {
+                    context: Context::new(
+                        &Op::End,
+                        Some(&Span {
+                            column: 15,
+                            file: 0,
+                            len: 3,
+                            line: 1
+                        })
+                    ),
+                    limit: MAX_ITERATIONS
                 }),
-                m.execute(Op::Divide)
+                result
             );
+        }
 
+        #[test]
+        fn while_false_skips_block() {
             let mut m = machine();
-            m.push(Data::String("garbage".into())).unwrap();
-            m.push(Data::U32(2)).unwrap();
-            assert_eq!(
-                Err(Error::InvalidType {
-                    got: Data::String("garbage".into()),
-                    instruction_pointer: 0
-                }),
-                m.execute(Op::Subtract)
+
+            let result = m.interpret(
+                "
+            6 6 6 6 makeColor
+            while false do
+                drop
+            end
+            ",
             );
+
+            assert_eq!(Ok((6, 6, 6, 6).into()), result);
+        }
+    }
+
+    mod execute_program {
+        use super::*;
+
+        #[test]
+        fn keeps_stack() {
+            let mut machine = machine();
+            machine
+                .execute_program(&Program::compile("1 2").unwrap())
+                .unwrap();
+            machine
+                .execute_program(&Program::compile("if true do + end").unwrap())
+                .unwrap();
+
+            assert_eq!(vec![Data::U32(3)], machine.stack_values());
         }
     }
 
@@ -1251,73 +2467,116 @@ mod tests {
         #[test]
         fn add() {
             let token = "+";
-            assert_eq!(Ok(Op::Add), machine().parse(token));
+            assert_eq!(Ok(Op::Add), PixelMachine::parse(token));
         }
 
         #[test]
         fn and() {
             let token = "&&";
-            assert_eq!(Ok(Op::And), machine().parse(token));
+            assert_eq!(Ok(Op::And), PixelMachine::parse(token));
         }
 
         #[test]
         fn bool_true() {
             let token = "true";
-            assert_eq!(Ok(Op::Data(Data::Bool(true))), machine().parse(token));
+            assert_eq!(Ok(Op::Data(Data::Bool(true))), PixelMachine::parse(token));
         }
 
         #[test]
         fn bool_false() {
             let token = "false";
-            assert_eq!(Ok(Op::Data(Data::Bool(false))), machine().parse(token));
+            assert_eq!(Ok(Op::Data(Data::Bool(false))), PixelMachine::parse(token));
         }
 
         #[test]
         fn dimensions() {
             let token = "dim";
-            assert_eq!(Ok(Op::Dimensions), machine().parse(token));
+            assert_eq!(Ok(Op::Dimensions), PixelMachine::parse(token));
         }
 
         #[test]
         fn divide() {
             let token = "/";
-            assert_eq!(Ok(Op::Divide), machine().parse(token));
+            assert_eq!(Ok(Op::Divide), PixelMachine::parse(token));
         }
 
         #[test]
         fn do_() {
             let token = "do";
-            assert_eq!(Ok(Op::Do), machine().parse(token));
+            assert_eq!(Ok(Op::Do), PixelMachine::parse(token));
         }
 
         #[test]
         fn drop() {
             let token = "drop";
-            assert_eq!(Ok(Op::Drop), machine().parse(token));
+            assert_eq!(Ok(Op::Drop), PixelMachine::parse(token));
         }
 
         #[test]
         fn dup() {
             let token = "dup";
-            assert_eq!(Ok(Op::Dup), machine().parse(token));
+            assert_eq!(Ok(Op::Dup), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn else_() {
+            let token = "else";
+            assert_eq!(Ok(Op::Else), PixelMachine::parse(token));
         }
 
         #[test]
         fn end() {
             let token = "end";
-            assert_eq!(Ok(Op::End), machine().parse(token));
+            assert_eq!(Ok(Op::End), PixelMachine::parse(token));
         }
 
         #[test]
         fn eq() {
             let token = "==";
-            assert_eq!(Ok(Op::Equal), machine().parse(token));
+            assert_eq!(Ok(Op::Equal), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn f32_requires_point() {
+            let token = "1e3";
+            assert_eq!(
+                Err(Error::UnhandledToken {
+                    context: Context {
+                        expected: vec![],
+                        op: "1e3".into(),
+                        span: None,
+                    },
+                    got: "1e3".into()
+                }),
+                PixelMachine::parse(token)
+            );
+        }
+
+        #[test]
+        fn f32_valid() {
+            let token = "-.5";
+            assert_eq!(Ok(Op::Data(Data::F32(-0.5))), PixelMachine::parse(token));
+
+            let token = "2.";
+            assert_eq!(Ok(Op::Data(Data::F32(2.0))), PixelMachine::parse(token));
         }
 
         #[test]
         fn frag_pos() {
             let token = "fragPos";
-            assert_eq!(Ok(Op::FragPos), machine().parse(token));
+            assert_eq!(Ok(Op::FragPos), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn frame() {
+            let token = "frame";
+            assert_eq!(Ok(Op::Frame), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn index() {
+            let token = "index";
+            assert_eq!(Ok(Op::Index), PixelMachine::parse(token));
         }
 
         #[test]
@@ -1325,100 +2584,262 @@ mod tests {
             let token = "garbageDay!!!";
             assert_eq!(
                 Err(Error::UnhandledToken {
+                    context: Context {
+                        expected: vec![],
+                        op: "garbageDay!!!".into(),
+                        span: None,
+                    },
                     got: "garbageDay!!!".into()
                 }),
-                machine().parse(token)
+                PixelMachine::parse(token)
             );
         }
 
         #[test]
         fn greater_than() {
             let token = ">";
-            assert_eq!(Ok(Op::GreaterThan), machine().parse(token));
+            assert_eq!(Ok(Op::GreaterThan), PixelMachine::parse(token));
         }
 
         #[test]
         fn greater_than_equal() {
             let token = ">=";
-            assert_eq!(Ok(Op::GreaterThanEqual), machine().parse(token));
+            assert_eq!(Ok(Op::GreaterThanEqual), PixelMachine::parse(token));
         }
 
         #[test]
         fn if_() {
             let token = "if";
-            assert_eq!(Ok(Op::If), machine().parse(token));
+            assert_eq!(Ok(Op::If), PixelMachine::parse(token));
         }
 
         #[test]
         fn less_than() {
             let token = "<";
-            assert_eq!(Ok(Op::LessThan), machine().parse(token));
+            assert_eq!(Ok(Op::LessThan), PixelMachine::parse(token));
         }
 
         #[test]
         fn less_than_equal() {
             let token = "<=";
-            assert_eq!(Ok(Op::LessThanEqual), machine().parse(token));
+            assert_eq!(Ok(Op::LessThanEqual), PixelMachine::parse(token));
         }
 
         #[test]
         fn make_color() {
             let token = "makeColor";
-            assert_eq!(Ok(Op::MakeColor), machine().parse(token));
+            assert_eq!(Ok(Op::MakeColor), PixelMachine::parse(token));
         }
 
         #[test]
         fn modulo() {
             let token = "%";
-            assert_eq!(Ok(Op::Modulo), machine().parse(token));
+            assert_eq!(Ok(Op::Modulo), PixelMachine::parse(token));
         }
 
         #[test]
         fn multiply() {
             let token = "*";
-            assert_eq!(Ok(Op::Multiply), machine().parse(token));
+            assert_eq!(Ok(Op::Multiply), PixelMachine::parse(token));
         }
 
         #[test]
         fn rot() {
             let token = "rot";
-            assert_eq!(Ok(Op::Rot), machine().parse(token));
+            assert_eq!(Ok(Op::Rot), PixelMachine::parse(token));
         }
 
         #[test]
         fn rot_n() {
             let token = "rotN";
-            assert_eq!(Ok(Op::RotN), machine().parse(token));
+            assert_eq!(Ok(Op::RotN), PixelMachine::parse(token));
         }
 
         #[test]
         fn subtract() {
             let token = "-";
-            assert_eq!(Ok(Op::Subtract), machine().parse(token));
+            assert_eq!(Ok(Op::Subtract), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn sample() {
+            let token = "sampleNearest";
+            assert_eq!(
+                Ok(Op::Sample {
+                    address: None,
+                    filter: Filter::Nearest
+                }),
+                PixelMachine::parse(token)
+            );
+
+            let token = "sampleBicubic.mirror";
+            assert_eq!(
+                Ok(Op::Sample {
+                    address: Some(Address::Mirror),
+                    filter: Filter::Bicubic
+                }),
+                PixelMachine::parse(token)
+            );
+        }
+
+        #[test]
+        fn sample_invalid_address() {
+            let token = "sampleNearest.wrap";
+            assert_eq!(
+                Err(Error::UnhandledToken {
+                    context: Context {
+                        expected: vec![],
+                        op: token.into(),
+                        span: None,
+                    },
+                    got: token.into()
+                }),
+                PixelMachine::parse(token)
+            );
+        }
+
+        #[test]
+        fn string() {
+            let token = r#""a \"b\"\t\\ c""#;
+            assert_eq!(
+                Ok(Op::Data(Data::String("a \"b\"\t\\ c".into()))),
+                PixelMachine::parse(token)
+            );
+        }
+
+        #[test]
+        fn string_invalid() {
+            let invalid = |token: &str, reason| {
+                assert_eq!(
+                    Err(Error::InvalidString {
+                        context: Context {
+                            expected: vec![],
+                            op: token.into(),
+                            span: None,
+                        },
+                        reason,
+                    }),
+                    PixelMachine::parse(token)
+                );
+            };
+
+            invalid(r#""abc"#, "is missing a closing quote");
+            invalid(r#""a"b"#, "has characters after the closing quote");
+            invalid(r#""\q""#, "has an unknown escape");
+        }
+
+        #[test]
+        fn swizzle() {
+            let token = ".yx";
+            assert_eq!(Ok(Op::Swizzle(vec![1, 0])), PixelMachine::parse(token));
+
+            let token = ".rgba";
+            assert_eq!(
+                Ok(Op::Swizzle(vec![0, 1, 2, 3])),
+                PixelMachine::parse(token)
+            );
+        }
+
+        #[test]
+        fn swizzle_invalid() {
+            for token in &[".", ".xg", ".xyzwx"] {
+                assert_eq!(
+                    Err(Error::UnhandledToken {
+                        context: Context {
+                            expected: vec![],
+                            op: token.to_string(),
+                            span: None,
+                        },
+                        got: token.to_string()
+                    }),
+                    PixelMachine::parse(token)
+                );
+            }
+        }
+
+        #[test]
+        fn texture() {
+            let token = "@mummy";
+            assert_eq!(Ok(Op::Texture("mummy".into())), PixelMachine::parse(token));
         }
 
         #[test]
         fn texture_pixel() {
             let token = "texturePixel";
-            assert_eq!(Ok(Op::TexturePixel), machine().parse(token));
+            assert_eq!(Ok(Op::TexturePixel), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn time() {
+            let token = "time";
+            assert_eq!(Ok(Op::Time), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn times() {
+            let token = "times";
+            assert_eq!(Ok(Op::Times), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn to_f32() {
+            let token = "toF32";
+            assert_eq!(Ok(Op::ToF32), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn to_u32() {
+            let token = "toU32";
+            assert_eq!(Ok(Op::ToU32), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn to_u8() {
+            let token = "toU8";
+            assert_eq!(Ok(Op::ToU8), PixelMachine::parse(token));
         }
 
         #[test]
         fn split_color() {
             let token = "splitColor";
-            assert_eq!(Ok(Op::SplitColor), machine().parse(token));
+            assert_eq!(Ok(Op::SplitColor), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn uniform() {
+            let token = "$threshold";
+            assert_eq!(
+                Ok(Op::Uniform("threshold".into())),
+                PixelMachine::parse(token)
+            );
+        }
+
+        #[test]
+        fn uniform_without_name() {
+            let token = "$";
+            assert_eq!(
+                Err(Error::UnhandledToken {
+                    context: Context {
+                        expected: vec![],
+                        op: token.into(),
+                        span: None,
+                    },
+                    got: token.into()
+                }),
+                PixelMachine::parse(token)
+            );
         }
 
         #[test]
         fn u8_valid() {
             let token = "0123";
-            assert_eq!(Ok(Op::Data(Data::U8(123))), machine().parse(token));
+            assert_eq!(Ok(Op::Data(Data::U8(123))), PixelMachine::parse(token));
         }
 
         #[test]
         fn u32_valid() {
             let token = "266";
-            assert_eq!(Ok(Op::Data(Data::U32(266))), machine().parse(token));
+            assert_eq!(Ok(Op::Data(Data::U32(266))), PixelMachine::parse(token));
         }
 
         #[test]
@@ -1426,11 +2847,28 @@ mod tests {
             let token = "-0123";
             assert_eq!(
                 Err(Error::UnhandledToken {
+                    context: Context {
+                        expected: vec![],
+                        op: "-0123".into(),
+                        span: None,
+                    },
                     got: "-0123".into()
                 }),
-                machine().parse(token)
+                PixelMachine::parse(token)
             );
         }
+
+        #[test]
+        fn vec3() {
+            let token = "vec3";
+            assert_eq!(Ok(Op::Vec3), PixelMachine::parse(token));
+        }
+
+        #[test]
+        fn while_() {
+            let token = "while";
+            assert_eq!(Ok(Op::While), PixelMachine::parse(token));
+        }
     }
 
     mod pop {
@@ -1447,7 +2885,12 @@ mod tests {
         #[test]
         fn pop_returns_underflow() {
             let mut m = machine();
-            assert_eq!(Err(Error::StackUnderflow), m.pop());
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::default()
+                }),
+                m.pop()
+            );
         }
 
         // Bool
@@ -1462,7 +2905,12 @@ mod tests {
         #[test]
         fn pop_bool_underflow() {
             let mut m = machine();
-            assert_eq!(Err(Error::StackUnderflow), m.pop_bool());
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::default()
+                }),
+                m.pop_bool()
+            );
         }
 
         #[test]
@@ -1472,8 +2920,8 @@ mod tests {
 
             assert_eq!(
                 Err(Error::InvalidType {
+                    context: Context::default(),
                     got: Data::Color((0, 0, 0).into()),
-                    instruction_pointer: 0
                 }),
                 m.pop_bool()
             );
@@ -1490,7 +2938,12 @@ mod tests {
         #[test]
         fn pop_color_underflow() {
             let mut m = machine();
-            assert_eq!(Err(Error::StackUnderflow), m.pop_color());
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::default()
+                }),
+                m.pop_color()
+            );
         }
 
         #[test]
@@ -1500,8 +2953,8 @@ mod tests {
 
             assert_eq!(
                 Err(Error::InvalidType {
+                    context: Context::default(),
                     got: Data::Bool(true),
-                    instruction_pointer: 0
                 }),
                 m.pop_color()
             );
@@ -1518,7 +2971,12 @@ mod tests {
         #[test]
         fn pop_string_underflow() {
             let mut m = machine();
-            assert_eq!(Err(Error::StackUnderflow), m.pop_string());
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::default()
+                }),
+                m.pop_string()
+            );
         }
 
         #[test]
@@ -1528,12 +2986,39 @@ mod tests {
 
             assert_eq!(
                 Err(Error::InvalidType {
+                    context: Context::default(),
                     got: Data::Bool(true),
-                    instruction_pointer: 0
                 }),
                 m.pop_string()
             );
         }
+        // F32
+        #[test]
+        fn pop_f32_converts_ints() {
+            let mut m = machine();
+            m.push(Data::U8(3)).unwrap();
+            m.push(Data::F32(0.5)).unwrap();
+            assert_eq!(Ok(0.5), m.pop_f32());
+            assert_eq!(Ok(3.0), m.pop_f32());
+        }
+
+        // Numbers
+        #[test]
+        fn pop_numbers_ints() {
+            let mut m = machine();
+            m.push(Data::U8(3)).unwrap();
+            m.push(Data::U32(4)).unwrap();
+            assert_eq!(Ok(Numbers::U32(4, 3)), m.pop_numbers());
+        }
+
+        #[test]
+        fn pop_numbers_promotes_floats() {
+            let mut m = machine();
+            m.push(Data::F32(0.5)).unwrap();
+            m.push(Data::U32(4)).unwrap();
+            assert_eq!(Ok(Numbers::F32(4.0, 0.5)), m.pop_numbers());
+        }
+
         // U32
         #[test]
         fn pop_u32() {
@@ -1552,7 +3037,12 @@ mod tests {
         #[test]
         fn pop_u32_underflow() {
             let mut m = machine();
-            assert_eq!(Err(Error::StackUnderflow), m.pop_u32());
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::default()
+                }),
+                m.pop_u32()
+            );
         }
 
         #[test]
@@ -1562,8 +3052,8 @@ mod tests {
 
             assert_eq!(
                 Err(Error::InvalidType {
+                    context: Context::default(),
                     got: Data::Bool(true),
-                    instruction_pointer: 0
                 }),
                 m.pop_u32()
             );
@@ -1594,7 +3084,12 @@ mod tests {
         #[test]
         fn pop_u8_underflow() {
             let mut m = machine();
-            assert_eq!(Err(Error::StackUnderflow), m.pop_u8());
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context::default()
+                }),
+                m.pop_u8()
+            );
         }
 
         #[test]
@@ -1604,8 +3099,8 @@ mod tests {
 
             assert_eq!(
                 Err(Error::InvalidType {
+                    context: Context::default(),
                     got: Data::Bool(true),
-                    instruction_pointer: 0
                 }),
                 m.pop_u8()
             );
@@ -1623,4 +3118,176 @@ mod tests {
             assert_eq!(Ok(true), m.pop_bool());
         }
     }
+
+    mod run {
+        use super::*;
+
+        #[test]
+        fn error_has_span() {
+            let program = Program::compile("1 2\n 0 8 /").unwrap();
+
+            assert_eq!(
+                Err(Error::DivideByZero {
+                    context: Context::new(
+                        &Op::Divide,
+                        Some(&Span {
+                            column: 6,
+                            file: 0,
+                            len: 1,
+                            line: 2
+                        })
+                    )
+                }),
+                machine().run(&program)
+            );
+        }
+
+        #[test]
+        fn recursion_limit() {
+            let program = Program::compile(": loop loop ; loop").unwrap();
+
+            assert_eq!(
+                Err(Error::RecursionLimit {
+                    context: Context::new(
+                        &Op::Call(0),
+                        Some(&Span {
+                            column: 8,
+                            file: 0,
+                            len: 4,
+                            line: 1
+                        })
+                    ),
+                    limit: MAX_CALL_DEPTH
+                }),
+                machine().run(&program)
+            );
+        }
+
+        #[test]
+        fn result_not_color() {
+            let program = Program::compile("1 2").unwrap();
+
+            assert_eq!(
+                Err(Error::InvalidType {
+                    context: Context {
+                        expected: vec![Type::Color],
+                        op: "data".into(),
+                        span: Some(Span {
+                            column: 3,
+                            file: 0,
+                            len: 1,
+                            line: 1
+                        })
+                    },
+                    got: Data::U8(2)
+                }),
+                machine().run(&program)
+            );
+        }
+
+        #[test]
+        fn returns_color() {
+            let program = Program::compile("1 2 3 4 makeColor").unwrap();
+
+            assert_eq!(Ok((1, 2, 3, 4).into()), machine().run(&program));
+        }
+
+        #[test]
+        fn word_calls() {
+            let program =
+                Program::compile(": double dup + ; 1 double 2 double 3 4 makeColor").unwrap();
+
+            assert_eq!(Ok((2, 4, 3, 4).into()), machine().run(&program));
+        }
+    }
+
+    mod run_outputs {
+        use super::*;
+
+        #[test]
+        fn push_order() {
+            let program = Program::compile("1 2 3 4 makeColor 5 6 7 8 makeColor").unwrap();
+
+            assert_eq!(
+                Ok(vec![(1, 2, 3, 4).into(), (5, 6, 7, 8).into()]),
+                machine().run_outputs(2, &program)
+            );
+        }
+
+        #[test]
+        fn underflow() {
+            let program = Program::compile("1 2 3 4 makeColor").unwrap();
+
+            assert_eq!(
+                Err(Error::StackUnderflow {
+                    context: Context {
+                        expected: vec![Type::Color; 2],
+                        op: "makeColor".into(),
+                        span: Some(Span {
+                            column: 9,
+                            file: 0,
+                            len: 9,
+                            line: 1
+                        })
+                    },
+                }),
+                machine().run_outputs(2, &program)
+            );
+        }
+    }
+
+    mod trace {
+        use super::*;
+
+        #[test]
+        fn stack_after_each_op() {
+            let program = Program::compile("1 2 + 0 0 255 makeColor").unwrap();
+            let mut steps = vec![];
+            let colors = machine().trace(1, &program, |step| steps.push(step));
+
+            assert_eq!(Ok(vec![(3, 0, 0, 255).into()]), colors);
+            assert_eq!(7, steps.len());
+            assert_eq!(
+                Step {
+                    instruction_pointer: 2,
+                    op: Op::Add,
+                    span: Some(Span {
+                        column: 5,
+                        file: 0,
+                        len: 1,
+                        line: 1
+                    }),
+                    stack: vec![Data::U32(3)],
+                },
+                steps[2]
+            );
+            assert_eq!(vec![Data::Color((3, 0, 0, 255).into())], steps[6].stack);
+        }
+
+        #[test]
+        fn steps_inside_if() {
+            let program = Program::compile("if true do 1 else 2 end 0 0 255 makeColor").unwrap();
+            let mut steps = vec![];
+            machine()
+                .trace(1, &program, |step| steps.push(step))
+                .unwrap();
+
+            let ops: Vec<&str> = steps.iter().map(|step| step.op.name()).collect();
+            assert_eq!(
+                vec![
+                    "if",
+                    "data",
+                    "do",
+                    "data",
+                    "else",
+                    "data",
+                    "data",
+                    "data",
+                    "makeColor"
+                ],
+                ops
+            );
+            assert_eq!(vec![Data::U8(1)], steps[3].stack);
+        }
+    }
 }
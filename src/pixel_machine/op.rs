@@ -1,111 +1,471 @@
-use super::{Data, Type};
+use super::{Address, Data, Filter, Type};
 
 /// Various operations that may be performed by the VM.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Op {
-    /// Adds two values on the stack.
+    /// Adds two values on the stack, component-wise for vectors.
     /// OP: `+ = [a:Number b:Number] -> [Number]`
     Add,
     /// Takes two bools and executes an `&&`.
     /// OP: `&& = [a:bool b:bool] -> [bool]`
     And,
-    /// Push some form of data onto the stack
-    /// OP: `[] -> [A]
+    /// Calls the user defined word starting at the given op.
+    /// OP: `word = [..] -> [..]`
+    Call(usize),
+    /// Push some form of data onto the stack.
+    /// OP: `[] -> [A]`
     Data(Data),
     /// Returns the height and width of the image.
     /// OP: `dim = [] -> [w:u32 h:u32]`
     Dimensions,
-    /// Divides a number by a second number.
+    /// Divides a number by a second number, component-wise for vectors.
     /// OP: `/ = [divisor:Number n:Number] -> [Number]`
     Divide,
-    /// Signals a bool for an if statement
+    /// Ends the condition of an `if`, `times` or `while` block.
     /// OP: `do = [] -> []`
     Do,
+    /// Takes the dot product of two vectors.
+    /// OP: `dot = [a:Vector b:Vector] -> [f32]`
+    Dot,
     /// Drops an item off the stack.
     /// OP: `drop = [A] -> []`
     Drop,
     /// Duplicates the top element of the stack.
     /// OP: `dup = [A] -> [A A]`
     Dup,
-    /// A end label for a conditional.
+    /// Starts the block of an `if` that executes when its condition is false.
+    /// OP: `else = [] -> []`
+    Else,
+    /// A end label for a conditional or loop.
     /// OP: `end = [] -> []`
     End,
     /// Checks whether two things are equal.
     /// OP: `== = [A A] -> [bool]`
     Equal,
     /// Puts the given fragment position onto the stack.
-    /// OP: `fragPos = [] -> [x:u32 y:u32]
+    /// OP: `fragPos = [] -> [x:u32 y:u32]`
     FragPos,
+    /// Puts the index of the frame being rendered onto the stack.
+    /// OP: `frame = [] -> [frame:u32]`
+    Frame,
     /// Checks whether a is greater than b.
     /// OP: `> = [a:Number b:Number] -> [bool]`
     GreaterThan,
     /// Checks whether a is greater than or equal to b.
     /// OP: `>= = [a:Number b:Number] -> [bool]`
     GreaterThanEqual,
-    /// If the top of the stack is true, execute the proceeding block.
-    /// Otherwise skip to the `end` op.
+    /// If the condition is true, execute the proceeding block.
+    /// Otherwise skip to the `else` or `end` op.
     /// OP: `if = [cond:bool] -> []`
     If,
+    /// Pushes the current iteration of the innermost `times` loop.
+    /// OP: `index = [] -> [i:u32]`
+    Index,
+    /// Returns the length of a vector.
+    /// OP: `length = [v:Vector] -> [f32]`
+    Length,
     /// Checks whether a is less than b.
     /// OP: `< = [a:Number b:Number] -> [bool]`
     LessThan,
     /// Checks whether a is less than or equal to b.
-    /// OP: `=< = [a:Number b:Number] -> [bool]`
+    /// OP: `<= = [a:Number b:Number] -> [bool]`
     LessThanEqual,
     /// Converts 4 u8's to a color.
     /// OP: `makeColor = [r:u8 g:u8 b:u8 a:u8] -> [color:Color]`
     MakeColor,
-    /// Performs a modulo on two numbers.
+    /// Performs a modulo on two numbers, component-wise for vectors.
     /// OP: `% = [n:Number modulus:Number] -> [Number]`
     Modulo,
-    /// Multiplies two numbers.
+    /// Multiplies two numbers, component-wise for vectors.
     /// OP: `* = [multiplier:Number n:Number] -> [Number]`
     Multiply,
+    /// Scales a vector to a length of 1.
+    /// OP: `normalize = [v:Vector] -> [Vector]`
+    Normalize,
+    /// Returns from a user defined word.
+    /// OP: `; = [] -> []`
+    Return,
     /// Rotates the top two elements of the stack.
     /// OP: `rot = [A B] -> [B A]`
     Rot,
     /// Rotates the top element and the Nth elements of the stack.
     /// OP: `rotN = [A .. B N] -> [B .. A]`
     RotN,
+    /// Samples a texture at normalized coordinates with the given filter.
+    /// The texture's addressing mode may be overridden, such as `sampleBilinear.clamp`.
+    /// OP: `sampleBilinear = [uv:vec2 textureIdx:u32] -> [color]`
+    Sample {
+        address: Option<Address>,
+        filter: Filter,
+    },
     /// Splits a color into each individual part.
-    /// OP: `splitColor = [c:color] -> [r:u8 g:u8 b:u8 a:u8]
+    /// OP: `splitColor = [c:color] -> [r:u8 g:u8 b:u8 a:u8]`
     SplitColor,
-    /// Subtracts the top two elements of the stack.
+    /// Splits a vector into each individual component.
+    /// OP: `splitVec = [v:Vector] -> [x:f32 ..]`
+    SplitVec,
+    /// Subtracts the top two elements of the stack, component-wise for vectors.
     /// OP: `- = [subtractor:Number n:Number] -> [Number]`
     Subtract,
+    /// Picks the given components of a vector, such as `.xy` or `.bgra`.
+    /// OP: `.xyzw = [v:Vector] -> [Vector]`
+    Swizzle(Vec<usize>),
+    /// Pushes the index of the named input, which must be defined by the config.
+    /// OP: `@name = [] -> [textureIdx:u32]`
+    Texture(String),
+    /// Looks up the index of the input with the given name.
+    /// OP: `texture = [name:String] -> [textureIdx:u32]`
+    TextureIndex,
     /// Loads a pixel from the given texture.
-    /// OP: `texturePixel = [x:u32 y:u32 textureIdx:u32] -> [color]
+    /// OP: `texturePixel = [x:u32 y:u32 textureIdx:u32] -> [color]`
     TexturePixel,
+    /// Puts the elapsed time of the frame being rendered in seconds onto the stack.
+    /// OP: `time = [] -> [seconds:f32]`
+    Time,
+    /// Executes the proceeding block the given number of times.
+    /// OP: `times = [count:u32] -> []`
+    Times,
+    /// Converts a vector of values between 0 and 1 to a color.
+    /// OP: `toColor = [v:vec4] -> [color]`
+    ToColor,
+    /// Converts a number to a float.
+    /// OP: `toF32 = [n:Number] -> [f32]`
+    ToF32,
+    /// Converts a number to a u32, truncating and saturating floats.
+    /// OP: `toU32 = [n:Number] -> [u32]`
+    ToU32,
+    /// Converts a number to a u8, saturating values out of range.
+    /// OP: `toU8 = [n:Number] -> [u8]`
+    ToU8,
+    /// Converts a color to a vector of values between 0 and 1.
+    /// OP: `toVec4 = [c:color] -> [vec4]`
+    ToVec4,
+    /// Pushes the value of the named uniform, which must be defined by the config.
+    /// OP: `$name = [] -> [A]`
+    Uniform(String),
+    /// Pushes the value of the uniform with the given name.
+    /// OP: `uniform = [name:String] -> [A]`
+    UniformValue,
+    /// Creates a vector from two numbers.
+    /// OP: `vec2 = [x:Number y:Number] -> [vec2]`
+    Vec2,
+    /// Creates a vector from three numbers.
+    /// OP: `vec3 = [x:Number y:Number z:Number] -> [vec3]`
+    Vec3,
+    /// Creates a vector from four numbers.
+    /// OP: `vec4 = [x:Number y:Number z:Number w:Number] -> [vec4]`
+    Vec4,
+    /// While the top of the stack is true, execute the proceeding block.
+    /// The condition is executed again after each iteration.
+    /// OP: `while = [cond:bool] -> []`
+    While,
 }
 
 impl Op {
+    /// Returns whether the op only reads and writes the stack, so it can be folded at compile time.
+    pub fn is_pure(&self) -> bool {
+        match self {
+            Op::Add
+            | Op::And
+            | Op::Divide
+            | Op::Dot
+            | Op::Drop
+            | Op::Dup
+            | Op::Equal
+            | Op::GreaterThan
+            | Op::GreaterThanEqual
+            | Op::Length
+            | Op::LessThan
+            | Op::LessThanEqual
+            | Op::MakeColor
+            | Op::Modulo
+            | Op::Multiply
+            | Op::Normalize
+            | Op::Rot
+            | Op::RotN
+            | Op::SplitColor
+            | Op::SplitVec
+            | Op::Subtract
+            | Op::Swizzle(_)
+            | Op::ToColor
+            | Op::ToF32
+            | Op::ToU32
+            | Op::ToU8
+            | Op::ToVec4
+            | Op::Vec2
+            | Op::Vec3
+            | Op::Vec4 => true,
+            Op::Call(_)
+            | Op::Data(_)
+            | Op::Dimensions
+            | Op::Do
+            | Op::Else
+            | Op::End
+            | Op::FragPos
+            | Op::Frame
+            | Op::If
+            | Op::Index
+            | Op::Return
+            | Op::Sample { .. }
+            | Op::Texture(_)
+            | Op::TextureIndex
+            | Op::TexturePixel
+            | Op::Time
+            | Op::Times
+            | Op::Uniform(_)
+            | Op::UniformValue
+            | Op::While => false,
+        }
+    }
+
+    /// Returns the name of the op as written in a program.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::And => "&&",
+            Op::Call(_) => "call",
+            Op::Data(_) => "data",
+            Op::Dimensions => "dim",
+            Op::Divide => "/",
+            Op::Do => "do",
+            Op::Dot => "dot",
+            Op::Drop => "drop",
+            Op::Dup => "dup",
+            Op::Else => "else",
+            Op::End => "end",
+            Op::Equal => "==",
+            Op::FragPos => "fragPos",
+            Op::Frame => "frame",
+            Op::GreaterThan => ">",
+            Op::GreaterThanEqual => ">=",
+            Op::If => "if",
+            Op::Index => "index",
+            Op::Length => "length",
+            Op::LessThan => "<",
+            Op::LessThanEqual => "<=",
+            Op::MakeColor => "makeColor",
+            Op::Modulo => "%",
+            Op::Multiply => "*",
+            Op::Normalize => "normalize",
+            Op::Return => ";",
+            Op::Rot => "rot",
+            Op::RotN => "rotN",
+            Op::Sample { filter, .. } => match filter {
+                Filter::Bicubic => "sampleBicubic",
+                Filter::Bilinear => "sampleBilinear",
+                Filter::Nearest => "sampleNearest",
+            },
+            Op::SplitColor => "splitColor",
+            Op::SplitVec => "splitVec",
+            Op::Subtract => "-",
+            Op::Swizzle(_) => "swizzle",
+            Op::Texture(_) => "@name",
+            Op::TextureIndex => "texture",
+            Op::TexturePixel => "texturePixel",
+            Op::Time => "time",
+            Op::Times => "times",
+            Op::ToColor => "toColor",
+            Op::ToF32 => "toF32",
+            Op::ToU32 => "toU32",
+            Op::ToU8 => "toU8",
+            Op::ToVec4 => "toVec4",
+            Op::Uniform(_) => "$name",
+            Op::UniformValue => "uniform",
+            Op::Vec2 => "vec2",
+            Op::Vec3 => "vec3",
+            Op::Vec4 => "vec4",
+            Op::While => "while",
+        }
+    }
+
+    /// Returns the outputs the given op pushes onto the stack.
+    pub fn outputs(&self) -> &'static [Type] {
+        match self {
+            Op::Add => &[Type::Number],
+            Op::And => &[Type::Bool],
+            Op::Call(_) => &[],
+            Op::Data(data) => match data {
+                Data::Bool(_) => &[Type::Bool],
+                Data::Color(_) => &[Type::Color],
+                Data::F32(_) => &[Type::F32],
+                Data::String(_) => &[Type::String],
+                Data::U32(_) => &[Type::U32],
+                Data::U8(_) => &[Type::U8],
+                Data::Vec2(_) => &[Type::Vec2],
+                Data::Vec3(_) => &[Type::Vec3],
+                Data::Vec4(_) => &[Type::Vec4],
+            },
+            Op::Dimensions => &[Type::U32, Type::U32],
+            Op::Divide => &[Type::Number],
+            Op::Do => &[],
+            Op::Dot => &[Type::F32],
+            Op::Drop => &[],
+            Op::Dup => &[Type::Any, Type::Any],
+            Op::Else => &[],
+            Op::End => &[],
+            Op::Equal => &[Type::Bool],
+            Op::FragPos => &[Type::U32, Type::U32],
+            Op::Frame => &[Type::U32],
+            Op::GreaterThan => &[Type::Bool],
+            Op::GreaterThanEqual => &[Type::Bool],
+            Op::If => &[],
+            Op::Index => &[Type::U32],
+            Op::Length => &[Type::F32],
+            Op::LessThan => &[Type::Bool],
+            Op::LessThanEqual => &[Type::Bool],
+            Op::MakeColor => &[Type::Color],
+            Op::Modulo => &[Type::Number],
+            Op::Multiply => &[Type::Number],
+            Op::Normalize => &[Type::Vector],
+            Op::Return => &[],
+            Op::Rot => &[Type::Any, Type::Any],
+            Op::RotN => &[Type::Any, Type::Any],
+            Op::Sample { .. } => &[Type::Color],
+            Op::SplitColor => &[Type::U8, Type::U8, Type::U8, Type::U8],
+            Op::SplitVec => &[Type::Any],
+            Op::Subtract => &[Type::Number],
+            Op::Swizzle(components) => match components.len() {
+                1 => &[Type::F32],
+                2 => &[Type::Vec2],
+                3 => &[Type::Vec3],
+                _ => &[Type::Vec4],
+            },
+            Op::Texture(_) => &[Type::U32],
+            Op::TextureIndex => &[Type::U32],
+            Op::TexturePixel => &[Type::Color],
+            Op::Time => &[Type::F32],
+            Op::Times => &[],
+            Op::ToColor => &[Type::Color],
+            Op::ToF32 => &[Type::F32],
+            Op::ToU32 => &[Type::U32],
+            Op::ToU8 => &[Type::U8],
+            Op::ToVec4 => &[Type::Vec4],
+            Op::Uniform(_) => &[Type::Any],
+            Op::UniformValue => &[Type::Any],
+            Op::Vec2 => &[Type::Vec2],
+            Op::Vec3 => &[Type::Vec3],
+            Op::Vec4 => &[Type::Vec4],
+            Op::While => &[],
+        }
+    }
+
     /// Returns the required inputs for the given op.
-    pub fn required_inputs(&self) -> &[Type] {
+    pub fn required_inputs(&self) -> &'static [Type] {
         match self {
-            Op::Add => todo!(),
+            Op::Add => &[Type::Number, Type::Number],
             Op::And => &[Type::Bool, Type::Bool],
+            Op::Call(_) => &[],
             Op::Data(_) => &[],
-            Op::Dimensions => todo!(),
-            Op::Divide => todo!(),
-            Op::Do => todo!(),
+            Op::Dimensions => &[],
+            Op::Divide => &[Type::Number, Type::Number],
+            Op::Do => &[],
+            Op::Dot => &[Type::Vector, Type::Vector],
             Op::Drop => &[Type::Any],
             Op::Dup => &[Type::Any],
-            Op::End => todo!(),
+            Op::Else => &[],
+            Op::End => &[],
             Op::Equal => &[Type::Any, Type::Any],
             Op::FragPos => &[],
-            Op::GreaterThan => &[Type::U32, Type::U32],
-            Op::GreaterThanEqual => &[Type::U32, Type::U32],
+            Op::Frame => &[],
+            Op::GreaterThan => &[Type::Number, Type::Number],
+            Op::GreaterThanEqual => &[Type::Number, Type::Number],
             Op::If => &[Type::Bool],
-            Op::LessThan => &[Type::U32, Type::U32],
-            Op::LessThanEqual => &[Type::U32, Type::U32],
+            Op::Index => &[],
+            Op::Length => &[Type::Vector],
+            Op::LessThan => &[Type::Number, Type::Number],
+            Op::LessThanEqual => &[Type::Number, Type::Number],
             Op::MakeColor => &[Type::U8, Type::U8, Type::U8, Type::U8],
-            Op::Modulo => todo!(),
-            Op::Multiply => todo!(),
+            Op::Modulo => &[Type::Number, Type::Number],
+            Op::Multiply => &[Type::Number, Type::Number],
+            Op::Normalize => &[Type::Vector],
+            Op::Return => &[],
             Op::Rot => &[Type::Any, Type::Any],
             Op::RotN => &[Type::Any, Type::Any, Type::U32],
+            Op::Sample { .. } => &[Type::Vec2, Type::U32],
             Op::SplitColor => &[Type::Color],
-            Op::Subtract => todo!(),
+            Op::SplitVec => &[Type::Vector],
+            Op::Subtract => &[Type::Number, Type::Number],
+            Op::Swizzle(components) => match components.iter().max() {
+                Some(2) => &[Type::Vec3],
+                Some(3) => &[Type::Vec4],
+                _ => &[Type::Vec2],
+            },
+            Op::Texture(_) => &[],
+            Op::TextureIndex => &[Type::String],
             Op::TexturePixel => &[Type::U32, Type::U32, Type::U32],
+            Op::Time => &[],
+            Op::Times => &[Type::U32],
+            Op::ToColor => &[Type::Vec4],
+            Op::ToF32 => &[Type::Number],
+            Op::ToU32 => &[Type::Number],
+            Op::ToU8 => &[Type::Number],
+            Op::ToVec4 => &[Type::Color],
+            Op::Uniform(_) => &[],
+            Op::UniformValue => &[Type::String],
+            Op::Vec2 => &[Type::Number, Type::Number],
+            Op::Vec3 => &[Type::Number, Type::Number, Type::Number],
+            Op::Vec4 => &[Type::Number, Type::Number, Type::Number, Type::Number],
+            Op::While => &[Type::Bool],
+        }
+    }
+
+    /// Returns the stack signature of the op.
+    pub fn signature(&self) -> &'static str {
+        match self {
+            Op::Add => "+ = [a:Number b:Number] -> [Number]",
+            Op::And => "&& = [a:bool b:bool] -> [bool]",
+            Op::Call(_) => "word = [..] -> [..]",
+            Op::Data(_) => "[] -> [A]",
+            Op::Dimensions => "dim = [] -> [w:u32 h:u32]",
+            Op::Divide => "/ = [divisor:Number n:Number] -> [Number]",
+            Op::Do => "do = [] -> []",
+            Op::Dot => "dot = [a:Vector b:Vector] -> [f32]",
+            Op::Drop => "drop = [A] -> []",
+            Op::Dup => "dup = [A] -> [A A]",
+            Op::Else => "else = [] -> []",
+            Op::End => "end = [] -> []",
+            Op::Equal => "== = [A A] -> [bool]",
+            Op::FragPos => "fragPos = [] -> [x:u32 y:u32]",
+            Op::Frame => "frame = [] -> [frame:u32]",
+            Op::GreaterThan => "> = [a:Number b:Number] -> [bool]",
+            Op::GreaterThanEqual => ">= = [a:Number b:Number] -> [bool]",
+            Op::If => "if = [cond:bool] -> []",
+            Op::Index => "index = [] -> [i:u32]",
+            Op::Length => "length = [v:Vector] -> [f32]",
+            Op::LessThan => "< = [a:Number b:Number] -> [bool]",
+            Op::LessThanEqual => "<= = [a:Number b:Number] -> [bool]",
+            Op::MakeColor => "makeColor = [r:u8 g:u8 b:u8 a:u8] -> [color:Color]",
+            Op::Modulo => "% = [n:Number modulus:Number] -> [Number]",
+            Op::Multiply => "* = [multiplier:Number n:Number] -> [Number]",
+            Op::Normalize => "normalize = [v:Vector] -> [Vector]",
+            Op::Return => "; = [] -> []",
+            Op::Rot => "rot = [A B] -> [B A]",
+            Op::RotN => "rotN = [A .. B N] -> [B .. A]",
+            Op::Sample { filter, .. } => match filter {
+                Filter::Bicubic => "sampleBicubic = [uv:vec2 textureIdx:u32] -> [color]",
+                Filter::Bilinear => "sampleBilinear = [uv:vec2 textureIdx:u32] -> [color]",
+                Filter::Nearest => "sampleNearest = [uv:vec2 textureIdx:u32] -> [color]",
+            },
+            Op::SplitColor => "splitColor = [c:color] -> [r:u8 g:u8 b:u8 a:u8]",
+            Op::SplitVec => "splitVec = [v:Vector] -> [x:f32 ..]",
+            Op::Subtract => "- = [subtractor:Number n:Number] -> [Number]",
+            Op::Swizzle(_) => ".xyzw = [v:Vector] -> [Vector]",
+            Op::Texture(_) => "@name = [] -> [textureIdx:u32]",
+            Op::TextureIndex => "texture = [name:String] -> [textureIdx:u32]",
+            Op::TexturePixel => "texturePixel = [x:u32 y:u32 textureIdx:u32] -> [color]",
+            Op::Time => "time = [] -> [seconds:f32]",
+            Op::Times => "times = [count:u32] -> []",
+            Op::ToColor => "toColor = [v:vec4] -> [color]",
+            Op::ToF32 => "toF32 = [n:Number] -> [f32]",
+            Op::ToU32 => "toU32 = [n:Number] -> [u32]",
+            Op::ToU8 => "toU8 = [n:Number] -> [u8]",
+            Op::ToVec4 => "toVec4 = [c:color] -> [vec4]",
+            Op::Uniform(_) => "$name = [] -> [A]",
+            Op::UniformValue => "uniform = [name:String] -> [A]",
+            Op::Vec2 => "vec2 = [x:Number y:Number] -> [vec2]",
+            Op::Vec3 => "vec3 = [x:Number y:Number z:Number] -> [vec3]",
+            Op::Vec4 => "vec4 = [x:Number y:Number z:Number w:Number] -> [vec4]",
+            Op::While => "while = [cond:bool] -> []",
         }
     }
 }
@@ -113,10 +473,186 @@ impl Op {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pixel_machine::PixelMachine;
+
+    mod name {
+        use super::*;
+
+        #[test]
+        fn data() {
+            assert_eq!("data", Op::Data(Data::Bool(true)).name());
+        }
+
+        #[test]
+        fn parses_back() {
+            let op = Op::TexturePixel;
+            assert_eq!(Ok(op.clone()), PixelMachine::parse(op.name()));
+
+            let op = Op::Sample {
+                address: None,
+                filter: Filter::Bicubic,
+            };
+            assert_eq!(Ok(op.clone()), PixelMachine::parse(op.name()));
+
+            let op = Op::LessThanEqual;
+            assert_eq!(Ok(op.clone()), PixelMachine::parse(op.name()));
+        }
+
+        #[test]
+        fn texture() {
+            let op = Op::Texture("name".into());
+            assert_eq!(Ok(op.clone()), PixelMachine::parse(op.name()));
+        }
+
+        #[test]
+        fn uniform() {
+            let op = Op::Uniform("name".into());
+            assert_eq!(Ok(op.clone()), PixelMachine::parse(op.name()));
+        }
+    }
+
+    mod outputs {
+        use super::*;
+
+        #[test]
+        fn add() {
+            let op = Op::Add;
+            let expected: &[Type] = &[Type::Number];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn and() {
+            let op = Op::And;
+            let expected: &[Type] = &[Type::Bool];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn data() {
+            let op = Op::Data(Data::U32(3));
+            let expected: &[Type] = &[Type::U32];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn dimensions() {
+            let op = Op::Dimensions;
+            let expected: &[Type] = &[Type::U32, Type::U32];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn drop() {
+            let op = Op::Drop;
+            let expected: &[Type] = &[];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn dup() {
+            let op = Op::Dup;
+            let expected: &[Type] = &[Type::Any, Type::Any];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn eq() {
+            let op = Op::Equal;
+            let expected: &[Type] = &[Type::Bool];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn frag_pos() {
+            let op = Op::FragPos;
+            let expected: &[Type] = &[Type::U32, Type::U32];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn frame() {
+            let op = Op::Frame;
+            let expected: &[Type] = &[Type::U32];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn if_() {
+            let op = Op::If;
+            let expected: &[Type] = &[];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn index() {
+            let op = Op::Index;
+            let expected: &[Type] = &[Type::U32];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn make_color() {
+            let op = Op::MakeColor;
+            let expected: &[Type] = &[Type::Color];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn split_color() {
+            let op = Op::SplitColor;
+            let expected: &[Type] = &[Type::U8, Type::U8, Type::U8, Type::U8];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn swizzle() {
+            let expected: &[Type] = &[Type::F32];
+            assert_eq!(expected, Op::Swizzle(vec![3]).outputs());
+
+            let expected: &[Type] = &[Type::Vec3];
+            assert_eq!(expected, Op::Swizzle(vec![0, 0, 1]).outputs());
+        }
+
+        #[test]
+        fn texture_pixel() {
+            let op = Op::TexturePixel;
+            let expected: &[Type] = &[Type::Color];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn time() {
+            let op = Op::Time;
+            let expected: &[Type] = &[Type::F32];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn to_f32() {
+            let op = Op::ToF32;
+            let expected: &[Type] = &[Type::F32];
+            assert_eq!(expected, op.outputs());
+        }
+
+        #[test]
+        fn uniform() {
+            let op = Op::Uniform("threshold".into());
+            let expected: &[Type] = &[Type::Any];
+            assert_eq!(expected, op.outputs());
+        }
+    }
 
     mod required_inputs {
         use super::*;
 
+        #[test]
+        fn add() {
+            let op = Op::Add;
+            let expected: &[Type] = &[Type::Number, Type::Number];
+            assert_eq!(expected, op.required_inputs());
+        }
+
         #[test]
         fn and() {
             let op = Op::And;
@@ -131,6 +667,27 @@ mod tests {
             assert_eq!(expected, op.required_inputs());
         }
 
+        #[test]
+        fn dimensions() {
+            let op = Op::Dimensions;
+            let expected: &[Type] = &[];
+            assert_eq!(expected, op.required_inputs());
+        }
+
+        #[test]
+        fn divide() {
+            let op = Op::Divide;
+            let expected: &[Type] = &[Type::Number, Type::Number];
+            assert_eq!(expected, op.required_inputs());
+        }
+
+        #[test]
+        fn do_() {
+            let op = Op::Do;
+            let expected: &[Type] = &[];
+            assert_eq!(expected, op.required_inputs());
+        }
+
         #[test]
         fn drop() {
             let op = Op::Drop;
@@ -145,6 +702,13 @@ mod tests {
             assert_eq!(expected, op.required_inputs());
         }
 
+        #[test]
+        fn end() {
+            let op = Op::End;
+            let expected: &[Type] = &[];
+            assert_eq!(expected, op.required_inputs());
+        }
+
         #[test]
         fn eq() {
             let op = Op::Equal;
@@ -159,17 +723,24 @@ mod tests {
             assert_eq!(expected, op.required_inputs());
         }
 
+        #[test]
+        fn frame() {
+            let op = Op::Frame;
+            let expected: &[Type] = &[];
+            assert_eq!(expected, op.required_inputs());
+        }
+
         #[test]
         fn greater_than() {
             let op = Op::GreaterThan;
-            let expected: &[Type] = &[Type::U32, Type::U32];
+            let expected: &[Type] = &[Type::Number, Type::Number];
             assert_eq!(expected, op.required_inputs());
         }
 
         #[test]
         fn greater_than_equal() {
             let op = Op::GreaterThanEqual;
-            let expected: &[Type] = &[Type::U32, Type::U32];
+            let expected: &[Type] = &[Type::Number, Type::Number];
             assert_eq!(expected, op.required_inputs());
         }
 
@@ -180,17 +751,24 @@ mod tests {
             assert_eq!(expected, op.required_inputs());
         }
 
+        #[test]
+        fn index() {
+            let op = Op::Index;
+            let expected: &[Type] = &[];
+            assert_eq!(expected, op.required_inputs());
+        }
+
         #[test]
         fn less_than() {
             let op = Op::LessThan;
-            let expected: &[Type] = &[Type::U32, Type::U32];
+            let expected: &[Type] = &[Type::Number, Type::Number];
             assert_eq!(expected, op.required_inputs());
         }
 
         #[test]
         fn less_than_equal() {
             let op = Op::LessThan;
-            let expected: &[Type] = &[Type::U32, Type::U32];
+            let expected: &[Type] = &[Type::Number, Type::Number];
             assert_eq!(expected, op.required_inputs());
         }
 
@@ -201,6 +779,20 @@ mod tests {
             assert_eq!(expected, op.required_inputs());
         }
 
+        #[test]
+        fn modulo() {
+            let op = Op::Modulo;
+            let expected: &[Type] = &[Type::Number, Type::Number];
+            assert_eq!(expected, op.required_inputs());
+        }
+
+        #[test]
+        fn multiply() {
+            let op = Op::Multiply;
+            let expected: &[Type] = &[Type::Number, Type::Number];
+            assert_eq!(expected, op.required_inputs());
+        }
+
         #[test]
         fn rot() {
             let op = Op::Rot;
@@ -222,11 +814,169 @@ mod tests {
             assert_eq!(expected, op.required_inputs());
         }
 
+        #[test]
+        fn subtract() {
+            let op = Op::Subtract;
+            let expected: &[Type] = &[Type::Number, Type::Number];
+            assert_eq!(expected, op.required_inputs());
+        }
+
+        #[test]
+        fn swizzle() {
+            let expected: &[Type] = &[Type::Vec2];
+            assert_eq!(expected, Op::Swizzle(vec![1, 0]).required_inputs());
+
+            let expected: &[Type] = &[Type::Vec4];
+            assert_eq!(expected, Op::Swizzle(vec![3]).required_inputs());
+        }
+
+        #[test]
+        fn texture_index() {
+            let op = Op::TextureIndex;
+            let expected: &[Type] = &[Type::String];
+            assert_eq!(expected, op.required_inputs());
+        }
+
         #[test]
         fn texture_pixel() {
             let op = Op::TexturePixel;
             let expected: &[Type] = &[Type::U32, Type::U32, Type::U32];
             assert_eq!(expected, op.required_inputs());
         }
+
+        #[test]
+        fn times() {
+            let op = Op::Times;
+            let expected: &[Type] = &[Type::U32];
+            assert_eq!(expected, op.required_inputs());
+        }
+
+        #[test]
+        fn to_u8() {
+            let op = Op::ToU8;
+            let expected: &[Type] = &[Type::Number];
+            assert_eq!(expected, op.required_inputs());
+        }
+
+        #[test]
+        fn while_() {
+            let op = Op::While;
+            let expected: &[Type] = &[Type::Bool];
+            assert_eq!(expected, op.required_inputs());
+        }
+    }
+
+    mod signature {
+        use super::*;
+
+        /// Returns the name of each variant of `Op` along with its `OP:` doc line, read from this file.
+        fn documented() -> Vec<(String, Option<String>)> {
+            let source = include_str!("op.rs");
+            let start = source.find("pub enum Op {").unwrap();
+            let end = start + source[start..].find("\n}").unwrap();
+
+            let mut documented = vec![];
+            let mut signature = None;
+            for line in source[start..end].lines().skip(1) {
+                if let Some(doc) = line.trim().strip_prefix("/// OP: ") {
+                    signature = Some(doc.trim_matches('`').to_string());
+                } else if let Some(variant) = line.strip_prefix("    ") {
+                    if variant.starts_with(char::is_uppercase) {
+                        documented.push((variant_name(variant), signature.take()));
+                    }
+                }
+            }
+
+            documented
+        }
+
+        /// Returns the name of the variant at the start of the given text.
+        fn variant_name(text: &str) -> String {
+            text.split(|c: char| !c.is_alphanumeric())
+                .next()
+                .unwrap_or_default()
+                .into()
+        }
+
+        #[test]
+        fn data() {
+            assert_eq!("[] -> [A]", Op::Data(Data::U8(1)).signature());
+        }
+
+        #[test]
+        fn matches_docs() {
+            let ops = [
+                Op::Add,
+                Op::And,
+                Op::Call(0),
+                Op::Data(Data::U8(0)),
+                Op::Dimensions,
+                Op::Divide,
+                Op::Do,
+                Op::Dot,
+                Op::Drop,
+                Op::Dup,
+                Op::Else,
+                Op::End,
+                Op::Equal,
+                Op::FragPos,
+                Op::Frame,
+                Op::GreaterThan,
+                Op::GreaterThanEqual,
+                Op::If,
+                Op::Index,
+                Op::Length,
+                Op::LessThan,
+                Op::LessThanEqual,
+                Op::MakeColor,
+                Op::Modulo,
+                Op::Multiply,
+                Op::Normalize,
+                Op::Return,
+                Op::Rot,
+                Op::RotN,
+                Op::Sample {
+                    address: None,
+                    filter: Filter::Bilinear,
+                },
+                Op::SplitColor,
+                Op::SplitVec,
+                Op::Subtract,
+                Op::Swizzle(vec![0]),
+                Op::Texture("name".into()),
+                Op::TextureIndex,
+                Op::TexturePixel,
+                Op::Time,
+                Op::Times,
+                Op::ToColor,
+                Op::ToF32,
+                Op::ToU32,
+                Op::ToU8,
+                Op::ToVec4,
+                Op::Uniform("name".into()),
+                Op::UniformValue,
+                Op::Vec2,
+                Op::Vec3,
+                Op::Vec4,
+                Op::While,
+            ];
+            let documented = documented();
+            assert_eq!(documented.len(), ops.len());
+
+            for (op, (name, signature)) in ops.iter().zip(documented) {
+                assert_eq!(name, variant_name(&format!("{:?}", op)));
+                assert_eq!(signature.as_deref(), Some(op.signature()), "{}", name);
+            }
+        }
+
+        #[test]
+        fn starts_with_name() {
+            let op = Op::MakeColor;
+            assert_eq!(
+                "makeColor = [r:u8 g:u8 b:u8 a:u8] -> [color:Color]",
+                op.signature()
+            );
+            assert!(op.signature().starts_with(op.name()));
+        }
     }
 }
@@ -0,0 +1,306 @@
+use super::{Data, Op, PixelMachine, Span};
+
+/// Returns how many ops from the start of the given ops are the same for every pixel,
+/// stopping at the first op that reads the pixel's position or branches.
+pub fn invariant_len(ops: &[Op]) -> usize {
+    ops.iter()
+        .take_while(|op| {
+            !matches!(
+                op,
+                Op::Call(_)
+                    | Op::Do
+                    | Op::Else
+                    | Op::End
+                    | Op::FragPos
+                    | Op::If
+                    | Op::Index
+                    | Op::Return
+                    | Op::Times
+                    | Op::While
+            )
+        })
+        .count()
+}
+
+/// Folds constant expressions, removes `rot rot` and `dup drop` pairs and
+/// replaces `if` blocks with constant conditions by the branch that is taken, until nothing changes.
+/// Returns the new entry, ops and spans, with every call pointing at the same word.
+/// The spans are empty when the given spans are.
+pub fn optimize(entry: usize, ops: &[Op], spans: &[Span]) -> (usize, Vec<Op>, Vec<Span>) {
+    let mut items: Vec<Item> = ops
+        .iter()
+        .enumerate()
+        .map(|(idx, op)| Item {
+            idx,
+            op: op.clone(),
+            span: spans.get(idx).copied(),
+        })
+        .collect();
+
+    while fold_constants(&mut items) | remove_dead_blocks(&mut items) | remove_noops(&mut items) {}
+
+    // Ops that were removed are replaced by the first op after them that survived.
+    let address = |old: usize| {
+        items
+            .iter()
+            .position(|item| item.idx >= old)
+            .unwrap_or(items.len())
+    };
+
+    let ops = items
+        .iter()
+        .map(|item| match item.op {
+            Op::Call(old) => Op::Call(address(old)),
+            ref op => op.clone(),
+        })
+        .collect();
+    let spans = items.iter().filter_map(|item| item.span).collect();
+
+    (address(entry), ops, spans)
+}
+
+/// Executes each pure op whose inputs are all constants on a scratch machine, replacing them with the result.
+/// Ops that fail, such as a division by zero, are kept so the error is raised when running.
+fn fold_constants(items: &mut Vec<Item>) -> bool {
+    let mut changed = false;
+    let mut idx = 0;
+    while idx < items.len() {
+        if !items[idx].op.is_pure() {
+            idx += 1;
+            continue;
+        }
+
+        let start = items[..idx]
+            .iter()
+            .rposition(|item| !matches!(item.op, Op::Data(_)))
+            .map_or(0, |position| position + 1);
+
+        let mut machine = PixelMachine::new(0, 0, 0, 0, vec![]);
+        for item in &items[start..idx] {
+            if let Op::Data(data) = &item.op {
+                machine.push(data.clone()).ok();
+            }
+        }
+        if machine.execute(items[idx].op.clone()).is_err() {
+            idx += 1;
+            continue;
+        }
+
+        // Constants below the ones the op consumed are left as they are.
+        let stack = machine.stack_values();
+        let kept = items[start..idx]
+            .iter()
+            .zip(&stack)
+            .take_while(|(item, data)| matches!(&item.op, Op::Data(constant) if constant == *data))
+            .count();
+        let first = start + kept;
+        let folded: Vec<Item> = stack[kept..]
+            .iter()
+            .map(|data| Item {
+                idx: items[first].idx,
+                op: Op::Data(data.clone()),
+                span: items[idx].span,
+            })
+            .collect();
+
+        let len = folded.len();
+        items.splice(first..=idx, folded);
+        idx = first + len;
+        changed = true;
+    }
+
+    changed
+}
+
+/// Replaces the first `if` block whose condition is a constant with the branch that is taken.
+fn remove_dead_blocks(items: &mut Vec<Item>) -> bool {
+    let op = |idx: usize| items.get(idx).map(|item: &Item| &item.op);
+    let dead = (0..items.len()).find_map(|idx| match (op(idx), op(idx + 1), op(idx + 2)) {
+        (Some(Op::If), Some(Op::Data(Data::Bool(condition))), Some(Op::Do)) => {
+            Some((idx, *condition))
+        }
+        _ => None,
+    });
+    let (start, condition) = match dead {
+        Some(dead) => dead,
+        None => return false,
+    };
+
+    let mut depth = 0;
+    let mut else_idx = None;
+    let mut end_idx = None;
+    for (idx, item) in items.iter().enumerate().skip(start + 3) {
+        match item.op {
+            Op::Else if depth == 0 => else_idx = Some(idx),
+            Op::End if depth == 0 => {
+                end_idx = Some(idx);
+                break;
+            }
+            Op::End => depth -= 1,
+            Op::If | Op::Times | Op::While => depth += 1,
+            _ => {}
+        }
+    }
+    let end_idx = match end_idx {
+        Some(end_idx) => end_idx,
+        None => return false,
+    };
+
+    let taken = match (condition, else_idx) {
+        (true, else_idx) => start + 3..else_idx.unwrap_or(end_idx),
+        (false, Some(else_idx)) => else_idx + 1..end_idx,
+        (false, None) => end_idx..end_idx,
+    };
+    let branch = items[taken].to_vec();
+    items.splice(start..=end_idx, branch);
+    true
+}
+
+/// Removes pairs of ops that cancel each other out, such as `rot rot` and `dup drop`.
+fn remove_noops(items: &mut Vec<Item>) -> bool {
+    let mut changed = false;
+    let mut idx = 0;
+    while idx + 1 < items.len() {
+        if matches!(
+            (&items[idx].op, &items[idx + 1].op),
+            (Op::Dup, Op::Drop) | (Op::Rot, Op::Rot)
+        ) {
+            items.drain(idx..idx + 2);
+            changed = true;
+            idx = idx.saturating_sub(1);
+        } else {
+            idx += 1;
+        }
+    }
+
+    changed
+}
+
+/// An op being optimized, along with the index it had before optimizing.
+#[derive(Clone, Debug)]
+struct Item {
+    idx: usize,
+    op: Op,
+    span: Option<Span>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel_machine::Program;
+
+    fn optimized(source: &str) -> Vec<Op> {
+        Program::compile(source).unwrap().optimize().ops().to_vec()
+    }
+
+    mod fold_constants {
+        use super::*;
+
+        #[test]
+        fn folds_nested_expressions() {
+            assert_eq!(vec![Op::Data(Data::U32(9))], optimized("1 2 + 3 *"));
+            assert_eq!(
+                vec![Op::FragPos, Op::Data(Data::F32(0.5)), Op::Multiply],
+                optimized("fragPos 2.0 1.0 / *")
+            );
+        }
+
+        #[test]
+        fn keeps_constants_below_inputs() {
+            assert_eq!(
+                vec![Op::Data(Data::U8(1)), Op::Data(Data::U32(5))],
+                optimized("1 2 3 +")
+            );
+        }
+
+        #[test]
+        fn keeps_failing_ops() {
+            assert_eq!(
+                vec![Op::Data(Data::U8(0)), Op::Data(Data::U8(1)), Op::Divide],
+                optimized("0 1 /")
+            );
+            assert_eq!(vec![Op::Add], optimized("+"));
+        }
+    }
+
+    mod optimize {
+        use super::*;
+
+        #[test]
+        fn remaps_calls() {
+            let program = Program::compile(": two 1 1 + ; : three 1 2 + ; three two")
+                .unwrap()
+                .optimize();
+            assert_eq!(
+                &[
+                    Op::Data(Data::U32(2)),
+                    Op::Return,
+                    Op::Data(Data::U32(3)),
+                    Op::Return,
+                    Op::Call(2),
+                    Op::Call(0)
+                ],
+                program.ops()
+            );
+            assert_eq!(4, program.entry());
+        }
+
+        #[test]
+        fn renders_same_color() {
+            let source = "fragPos rot rot * 2 2 * + toU8 dup dup 255 makeColor";
+            let program = Program::compile(source).unwrap();
+            for (x, y) in [(0, 0), (3, 1)] {
+                assert_eq!(
+                    PixelMachine::new(x, y, 4, 4, vec![]).run(&program),
+                    PixelMachine::new(x, y, 4, 4, vec![]).run(&program.optimize())
+                );
+            }
+        }
+    }
+
+    mod remove_dead_blocks {
+        use super::*;
+
+        #[test]
+        fn keeps_taken_branch() {
+            assert_eq!(
+                vec![Op::FragPos],
+                optimized("if 1 1 == do fragPos else frame end")
+            );
+            assert_eq!(
+                vec![Op::Frame],
+                optimized("if 1 2 == do fragPos else frame end")
+            );
+            assert_eq!(
+                Vec::<Op>::new(),
+                optimized("if false do if true do fragPos end end")
+            );
+        }
+
+        #[test]
+        fn keeps_unknown_conditions() {
+            assert_eq!(
+                vec![
+                    Op::If,
+                    Op::Frame,
+                    Op::Data(Data::U8(0)),
+                    Op::Equal,
+                    Op::Do,
+                    Op::FragPos,
+                    Op::End
+                ],
+                optimized("if frame 0 == do fragPos end")
+            );
+        }
+    }
+
+    mod remove_noops {
+        use super::*;
+
+        #[test]
+        fn removes_pairs() {
+            assert_eq!(vec![Op::FragPos], optimized("fragPos rot rot dup drop"));
+            assert_eq!(vec![Op::FragPos], optimized("fragPos dup rot rot drop"));
+        }
+    }
+}
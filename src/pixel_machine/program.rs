@@ -0,0 +1,1307 @@
+use super::{checker, optimizer, parse_string, Context, Data, Error, Op, PixelMachine};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The directive replaced by the tokens of another file.
+const INCLUDE: &str = "include";
+
+/// Tokens along with where they are in the source.
+type Tokens<'a> = Vec<(Span, &'a str)>;
+
+/// The various kinds of blocks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockKind {
+    If,
+    Times,
+    While,
+}
+
+/// A block that has been matched up at compile time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Block {
+    /// The index of the block's `do`.
+    pub do_idx: usize,
+    /// The index of the block's `else`, if it has one.
+    pub else_idx: Option<usize>,
+    /// The index of the block's `end`.
+    pub end_idx: usize,
+    pub kind: BlockKind,
+    /// The index of the op that opens the block.
+    pub start_idx: usize,
+}
+
+/// A compiled program that may be shared across many pixel machines.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    /// The block each op opens, branches or closes.
+    blocks: Arc<Vec<Option<Block>>>,
+    /// The index of the first op outside of a word definition.
+    entry: usize,
+    ops: Arc<Vec<Op>>,
+    /// The location of each op in the source.
+    spans: Arc<Vec<Span>>,
+    /// The values of the uniforms bound to the program.
+    uniforms: Arc<HashMap<String, Data>>,
+}
+
+impl Program {
+    /// Replaces every named input with its index and every uniform with its value,
+    /// failing on the first name that is not defined.
+    /// The uniforms are kept for uniforms looked up by name when running.
+    pub fn bind(
+        &self,
+        textures: &[String],
+        uniforms: &HashMap<String, Data>,
+    ) -> Result<Self, Error> {
+        let ops = self
+            .ops
+            .iter()
+            .enumerate()
+            .map(|(idx, op)| match op {
+                Op::Texture(name) => match textures.iter().position(|texture| texture == name) {
+                    Some(index) => Ok(Op::Data(Data::U32(index as u32))),
+                    None => Err(Error::UndefinedTexture {
+                        context: Context::new(op, self.spans.get(idx)),
+                        name: name.clone(),
+                    }),
+                },
+                Op::Uniform(name) => match uniforms.get(name) {
+                    Some(data) => Ok(Op::Data(data.clone())),
+                    None => Err(Error::UndefinedUniform {
+                        context: Context::new(op, self.spans.get(idx)),
+                        name: name.clone(),
+                    }),
+                },
+                op => Ok(op.clone()),
+            })
+            .collect::<Result<Vec<Op>, Error>>()?;
+
+        Ok(Self {
+            ops: Arc::new(ops),
+            uniforms: Arc::new(uniforms.clone()),
+            ..self.clone()
+        })
+    }
+
+    /// Returns the block the op at the given index opens, branches or closes.
+    pub fn block(&self, idx: usize) -> Option<Block> {
+        self.blocks.get(idx).copied().flatten()
+    }
+
+    /// Statically checks the program for type errors and stack underflows.
+    /// The program must leave a color on the stack for each output.
+    pub fn check(&self, outputs: usize) -> Result<(), Error> {
+        checker::check(self.entry, self.ops(), outputs, self.spans())
+    }
+
+    /// Compiles the given source into a program, including files relative to the working directory.
+    pub fn compile(source: &str) -> Result<Self, Error> {
+        Self::compile_file(Path::new(""), source, &mut Sources::default())
+    }
+
+    /// Compiles the source of the file at the given path into a program.
+    /// Each `include "file.das"` is replaced by the included file, resolved relative to the including file.
+    /// A file is only included the first time, and every file read is added to `sources`.
+    /// Words are laid out first, each ending in a return, followed by the rest of the program.
+    pub fn compile_file(path: &Path, source: &str, sources: &mut Sources) -> Result<Self, Error> {
+        let file = sources.add(path, source.into());
+        let mut includes = vec![];
+        load_includes(file, &mut includes, sources, &mut vec![file])?;
+
+        let (main, words) = split_words(expand(file, &includes, sources))?;
+
+        let mut addresses = HashMap::new();
+        let mut entry = 0;
+        for word in words.iter() {
+            addresses.insert(word.name, entry);
+            entry += word.body.len() + 1;
+        }
+
+        let parse = |span: &Span, token: &str| match addresses.get(token) {
+            Some(address) => Ok(Op::Call(*address)),
+            None => PixelMachine::parse(token).map_err(|e| e.with_span(Some(span))),
+        };
+
+        let mut ops = vec![];
+        let mut spans = vec![];
+        for word in words.iter() {
+            for (span, token) in word.body.iter() {
+                ops.push(parse(span, token)?);
+                spans.push(*span);
+            }
+
+            ops.push(Op::Return);
+            spans.push(word.end);
+        }
+
+        for (span, token) in main.iter() {
+            ops.push(parse(span, token)?);
+            spans.push(*span);
+        }
+
+        Self::new(entry, ops, spans)
+    }
+
+    /// Returns the index of the first op outside of a word definition.
+    pub fn entry(&self) -> usize {
+        self.entry
+    }
+
+    /// Runs the ops at the start of the program that are the same for every pixel once on the given machine,
+    /// replacing them with the values they leave on the stack.
+    /// The program is returned unchanged when those ops fail so every pixel reports the error.
+    pub fn hoist(&self, mut machine: PixelMachine) -> Self {
+        let end = self.entry + optimizer::invariant_len(&self.ops[self.entry..]);
+        if end == self.entry {
+            return self.clone();
+        }
+
+        let prefix = Self {
+            blocks: Arc::new(vec![None; end - self.entry]),
+            entry: 0,
+            ops: Arc::new(self.ops[self.entry..end].to_vec()),
+            spans: Arc::new(self.spans.get(self.entry..end).unwrap_or_default().to_vec()),
+            uniforms: self.uniforms.clone(),
+        };
+        if machine.execute_program(&prefix).is_err() {
+            return self.clone();
+        }
+
+        let stack = machine.stack_values();
+        let span = self.spans.get(end - 1).copied();
+        let ops: Vec<Op> = self.ops[..self.entry]
+            .iter()
+            .cloned()
+            .chain(stack.iter().cloned().map(Op::Data))
+            .chain(self.ops[end..].iter().cloned())
+            .collect();
+        let spans: Vec<Span> = self
+            .spans
+            .get(..self.entry)
+            .unwrap_or_default()
+            .iter()
+            .copied()
+            .chain(stack.iter().filter_map(|_| span))
+            .chain(self.spans.get(end..).unwrap_or_default().iter().copied())
+            .collect();
+        let blocks = resolve_blocks(&ops, &spans).expect("hoisting keeps blocks balanced");
+
+        Self {
+            blocks: Arc::new(blocks),
+            ops: Arc::new(ops),
+            spans: Arc::new(spans),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the ops of the program, one per line along with its index, marking where the entry is.
+    pub fn listing(&self) -> String {
+        let mut listing = String::new();
+        for (idx, op) in self.ops.iter().enumerate() {
+            if idx == self.entry {
+                listing.push_str("entry:\n");
+            }
+            listing.push_str(&format!("{:>6} {:?}\n", idx, op));
+        }
+
+        listing
+    }
+
+    /// Creates a program from ops laid out like a compiled one, matching up its blocks.
+    /// The spans may be empty when the locations of the ops are unknown.
+    pub fn new(entry: usize, ops: Vec<Op>, spans: Vec<Span>) -> Result<Self, Error> {
+        let blocks = resolve_blocks(&ops, &spans)?;
+
+        Ok(Self {
+            blocks: Arc::new(blocks),
+            entry,
+            ops: Arc::new(ops),
+            spans: Arc::new(spans),
+            uniforms: Arc::default(),
+        })
+    }
+
+    /// Returns the ops that make up the program.
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Folds constant expressions, removes ops that cancel each other out
+    /// and replaces `if` blocks with constant conditions by the branch that is taken.
+    pub fn optimize(&self) -> Self {
+        let (entry, ops, spans) = optimizer::optimize(self.entry, &self.ops, &self.spans);
+        let blocks = resolve_blocks(&ops, &spans).expect("optimizing keeps blocks balanced");
+
+        Self {
+            blocks: Arc::new(blocks),
+            entry,
+            ops: Arc::new(ops),
+            spans: Arc::new(spans),
+            uniforms: self.uniforms.clone(),
+        }
+    }
+
+    /// Returns the location of each op in the source.
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// Returns the value of the uniform with the given name.
+    pub fn uniform(&self, name: &str) -> Option<&Data> {
+        self.uniforms.get(name)
+    }
+}
+
+/// Returns whether the source opens more blocks or word definitions than it closes, so more source may finish it.
+pub(crate) fn is_unfinished(source: &str) -> bool {
+    let mut depth = 0usize;
+    let mut defining = false;
+    for (_, token) in tokenize(0, source) {
+        match token {
+            ":" => defining = true,
+            ";" => defining = false,
+            "end" => depth = depth.saturating_sub(1),
+            "if" | "times" | "while" => depth += 1,
+            _ => {}
+        }
+    }
+
+    defining || depth > 0
+}
+
+/// Returns the tokens of the file with each `include` replaced by the tokens of the file it adds.
+fn expand<'a>(file: usize, includes: &[Vec<Option<usize>>], sources: &'a Sources) -> Tokens<'a> {
+    let mut added = includes.get(file).into_iter().flatten();
+    let mut tokens = vec![];
+    let mut file_tokens = tokenize(file, sources.text(file)).into_iter();
+    while let Some((span, token)) = file_tokens.next() {
+        if token != INCLUDE {
+            tokens.push((span, token));
+            continue;
+        }
+
+        file_tokens.next();
+        if let Some(Some(included)) = added.next() {
+            tokens.extend(expand(*included, includes, sources));
+        }
+    }
+
+    tokens
+}
+
+/// Reads every file the given file includes, depth first, recording the file each `include` adds.
+/// Files that were already included add nothing, and files that include themselves are an error.
+fn load_includes(
+    file: usize,
+    includes: &mut Vec<Vec<Option<usize>>>,
+    sources: &mut Sources,
+    stack: &mut Vec<usize>,
+) -> Result<(), Error> {
+    let directives = {
+        let mut directives = vec![];
+        let mut tokens = tokenize(file, sources.text(file)).into_iter();
+        while let Some((span, token)) = tokens.next() {
+            if token == INCLUDE {
+                let path = tokens.next().and_then(|(_, path)| parse_string(path).ok());
+                directives.push((span, path));
+            }
+        }
+
+        directives
+    };
+
+    let mut added = vec![];
+    for (span, path) in directives {
+        let include_error = |reason: String| Error::InvalidInclude {
+            context: Context {
+                expected: vec![],
+                op: INCLUDE.into(),
+                span: Some(span),
+            },
+            reason,
+        };
+
+        let path = match path {
+            Some(path) => sources
+                .path(file)
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(path),
+            None => return Err(include_error("`include` requires a quoted path".into())),
+        };
+
+        let text = std::fs::read_to_string(sources.directory.join(&path))
+            .map_err(|e| include_error(format!("could not include `{}`: {}", path.display(), e)))?;
+        let canonical = sources.canonical(&path);
+        match sources.files.iter().position(|f| f.canonical == canonical) {
+            Some(included) if included == file => {
+                return Err(include_error(format!(
+                    "`{}` includes itself",
+                    path.display()
+                )))
+            }
+            Some(included) if stack.contains(&included) => {
+                return Err(include_error(format!(
+                    "`{}` includes itself through `{}`",
+                    path.display(),
+                    sources.path(file).display()
+                )))
+            }
+            Some(_) => added.push(None),
+            None => {
+                let included = sources.add(&path, text);
+                stack.push(included);
+                load_includes(included, includes, sources, stack)?;
+                stack.pop();
+                added.push(Some(included));
+            }
+        }
+    }
+
+    if includes.len() <= file {
+        includes.resize(file + 1, vec![]);
+    }
+    includes[file] = added;
+
+    Ok(())
+}
+
+/// Matches up every `if`, `times` and `while` with its `do`, `else` and `end`.
+fn resolve_blocks(ops: &[Op], spans: &[Span]) -> Result<Vec<Option<Block>>, Error> {
+    let unbalanced = |idx: usize, missing: &'static str| Error::UnbalancedBlock {
+        context: Context::new(&ops[idx], spans.get(idx)),
+        missing,
+    };
+
+    let mut blocks = vec![None; ops.len()];
+    let mut open: Vec<OpenBlock> = vec![];
+
+    for (idx, op) in ops.iter().enumerate() {
+        match op {
+            Op::Do => match open.last_mut() {
+                Some(OpenBlock {
+                    do_idx: do_idx @ None,
+                    ..
+                }) => *do_idx = Some(idx),
+                _ => return Err(unbalanced(idx, "if")),
+            },
+            Op::Else => match open.last_mut() {
+                Some(OpenBlock {
+                    do_idx: Some(_),
+                    else_idx: else_idx @ None,
+                    kind: BlockKind::If,
+                    ..
+                }) => *else_idx = Some(idx),
+                Some(OpenBlock { do_idx: None, .. }) => return Err(unbalanced(idx, "do")),
+                _ => return Err(unbalanced(idx, "if")),
+            },
+            Op::End => match open.pop() {
+                Some(OpenBlock {
+                    do_idx: Some(do_idx),
+                    else_idx,
+                    kind,
+                    start_idx,
+                }) => {
+                    let block = Block {
+                        do_idx,
+                        else_idx,
+                        end_idx: idx,
+                        kind,
+                        start_idx,
+                    };
+                    blocks[start_idx] = Some(block);
+                    blocks[do_idx] = Some(block);
+                    if let Some(else_idx) = else_idx {
+                        blocks[else_idx] = Some(block);
+                    }
+                    blocks[idx] = Some(block);
+                }
+                Some(OpenBlock { start_idx, .. }) => return Err(unbalanced(start_idx, "do")),
+                None => return Err(unbalanced(idx, "if")),
+            },
+            Op::If => open.push(OpenBlock::new(BlockKind::If, idx)),
+            Op::Return => {
+                // Blocks may not span multiple words.
+                if let Some(block) = open.pop() {
+                    return Err(unbalanced(block.start_idx, block.missing()));
+                }
+            }
+            Op::Times => open.push(OpenBlock::new(BlockKind::Times, idx)),
+            Op::While => open.push(OpenBlock::new(BlockKind::While, idx)),
+            _ => {}
+        }
+    }
+
+    match open.pop() {
+        Some(block) => Err(unbalanced(block.start_idx, block.missing())),
+        None => Ok(blocks),
+    }
+}
+
+/// Splits the definitions of words out from the rest of the tokens.
+/// A word is defined as `: name ... ;`.
+fn split_words(tokens: Tokens<'_>) -> Result<(Tokens<'_>, Vec<Word<'_>>), Error> {
+    const DEFINITION_END: &str = ";";
+    const DEFINITION_START: &str = ":";
+
+    let context = |op: &str, span: &Span| Context {
+        expected: vec![],
+        op: op.into(),
+        span: Some(*span),
+    };
+
+    let mut main = vec![];
+    let mut words: Vec<Word> = vec![];
+
+    let mut tokens = tokens.into_iter();
+    while let Some((span, token)) = tokens.next() {
+        match token {
+            DEFINITION_START => {
+                let (name_span, name) = match tokens.next() {
+                    Some(name) => name,
+                    None => {
+                        return Err(Error::InvalidWord {
+                            context: context(token, &span),
+                            reason: "is missing a name",
+                        })
+                    }
+                };
+
+                let reason = if name == DEFINITION_START || name == DEFINITION_END {
+                    Some("is missing a name")
+                } else if PixelMachine::parse(name).is_ok() {
+                    Some("is already an op")
+                } else if words.iter().any(|word| word.name == name) {
+                    Some("is already defined")
+                } else {
+                    None
+                };
+
+                if let Some(reason) = reason {
+                    return Err(Error::InvalidWord {
+                        context: context(name, &name_span),
+                        reason,
+                    });
+                }
+
+                let mut body = vec![];
+                let end = loop {
+                    match tokens.next() {
+                        Some((end, DEFINITION_END)) => break end,
+                        Some((_, DEFINITION_START)) | None => {
+                            return Err(Error::UnbalancedBlock {
+                                context: context(token, &span),
+                                missing: DEFINITION_END,
+                            })
+                        }
+                        Some(body_token) => body.push(body_token),
+                    }
+                };
+
+                words.push(Word { body, end, name });
+            }
+            DEFINITION_END => {
+                return Err(Error::UnbalancedBlock {
+                    context: context(token, &span),
+                    missing: DEFINITION_START,
+                })
+            }
+            _ => main.push((span, token)),
+        }
+    }
+
+    Ok((main, words))
+}
+
+/// Splits the source of the given file into tokens, skipping comments.
+/// Tokens starting with a quote run until the closing quote, keeping whitespace and escapes.
+fn tokenize(file: usize, source: &str) -> Tokens<'_> {
+    const COMMENT_START: char = '#';
+    const ESCAPE: char = '\\';
+    const QUOTE: char = '"';
+
+    let mut tokens = vec![];
+    for (line_idx, line) in source.lines().enumerate() {
+        let mut escaped = false;
+        let mut quoted = false;
+        let mut start = None;
+        for (column_idx, (byte_idx, c)) in line
+            .char_indices()
+            .chain(std::iter::once((line.len(), ' ')))
+            .enumerate()
+        {
+            let end = byte_idx == line.len() || (!quoted && c == COMMENT_START);
+            if quoted && !end {
+                if escaped {
+                    escaped = false;
+                } else if c == ESCAPE {
+                    escaped = true;
+                } else if c == QUOTE {
+                    quoted = false;
+                }
+
+                continue;
+            }
+
+            match (start, end || c.is_whitespace()) {
+                (None, false) => {
+                    quoted = c == QUOTE;
+                    start = Some((column_idx, byte_idx));
+                }
+                (Some((start_column, start_byte)), true) => {
+                    tokens.push((
+                        Span {
+                            column: start_column + 1,
+                            file,
+                            len: column_idx - start_column,
+                            line: line_idx + 1,
+                        },
+                        &line[start_byte..byte_idx],
+                    ));
+                    start = None;
+                }
+                _ => {}
+            }
+
+            if end {
+                break;
+            }
+        }
+    }
+
+    tokens
+}
+
+/// The files a program was compiled from, which spans index by their `file`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sources {
+    /// The directory the paths of the files are relative to.
+    directory: PathBuf,
+    files: Vec<Source>,
+}
+
+impl Sources {
+    /// Adds a file, returning its index.
+    pub(crate) fn add(&mut self, path: &Path, text: String) -> usize {
+        self.files.push(Source {
+            canonical: self.canonical(path),
+            path: path.to_path_buf(),
+            text,
+        });
+
+        self.files.len() - 1
+    }
+
+    /// Returns the path that identifies a file, regardless of how it was included.
+    fn canonical(&self, path: &Path) -> PathBuf {
+        let path = self.directory.join(path);
+        std::fs::canonicalize(&path).unwrap_or(path)
+    }
+
+    /// Creates an empty set of files, whose paths are relative to the given directory.
+    pub fn new(directory: &Path) -> Self {
+        Self {
+            directory: directory.to_path_buf(),
+            files: vec![],
+        }
+    }
+
+    /// Returns the path of the given file, relative to the directory.
+    pub fn path(&self, file: usize) -> &Path {
+        self.files
+            .get(file)
+            .map(|source| source.path.as_path())
+            .unwrap_or_else(|| Path::new(""))
+    }
+
+    /// Renders the error as a diagnostic pointing into the file it occurred in.
+    pub fn render(&self, error: &Error) -> String {
+        let file = error.context().span.map(|span| span.file).unwrap_or(0);
+        error.render(&self.path(file).display().to_string(), self.text(file))
+    }
+
+    /// Returns the source code of the given file.
+    pub fn text(&self, file: usize) -> &str {
+        self.files
+            .get(file)
+            .map(|source| source.text.as_str())
+            .unwrap_or_default()
+    }
+}
+
+/// A location in the source.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    /// The column the span starts on, starting at 1.
+    pub column: usize,
+    /// The index of the file the span is in, where the compiled file is 0.
+    pub file: usize,
+    /// The number of characters in the span.
+    pub len: usize,
+    /// The line the span is on, starting at 1.
+    pub line: usize,
+}
+
+/// A block that has been opened but not yet closed.
+#[derive(Debug)]
+struct OpenBlock {
+    do_idx: Option<usize>,
+    else_idx: Option<usize>,
+    kind: BlockKind,
+    start_idx: usize,
+}
+
+impl OpenBlock {
+    /// Opens a new block of the given kind.
+    fn new(kind: BlockKind, start_idx: usize) -> Self {
+        Self {
+            do_idx: None,
+            else_idx: None,
+            kind,
+            start_idx,
+        }
+    }
+
+    /// Returns the op the block is missing to be closed.
+    fn missing(&self) -> &'static str {
+        match self.do_idx {
+            Some(_) => "end",
+            None => "do",
+        }
+    }
+}
+
+/// A file a program was compiled from.
+#[derive(Clone, Debug, PartialEq)]
+struct Source {
+    /// The path that identifies the file, regardless of how it was included.
+    canonical: PathBuf,
+    /// The path the file was included by, relative to the directory of the sources.
+    path: PathBuf,
+    text: String,
+}
+
+/// A user defined word.
+#[derive(Debug, PartialEq)]
+struct Word<'a> {
+    body: Tokens<'a>,
+    /// The location of the `;` ending the definition.
+    end: Span,
+    name: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod bind {
+        use super::*;
+
+        #[test]
+        fn replaces_textures() {
+            let textures = ["mummy".to_string(), "test".to_string()];
+
+            let program = Program::compile("@test @mummy").unwrap();
+            assert_eq!(
+                &[Op::Data(Data::U32(1)), Op::Data(Data::U32(0))],
+                program.bind(&textures, &HashMap::new()).unwrap().ops()
+            );
+        }
+
+        #[test]
+        fn replaces_uniforms() {
+            let mut uniforms = HashMap::new();
+            uniforms.insert("threshold".to_string(), Data::F32(0.5));
+
+            let program = Program::compile("$threshold 1").unwrap();
+            assert_eq!(
+                &[Op::Data(Data::F32(0.5)), Op::Data(Data::U8(1))],
+                program.bind(&[], &uniforms).unwrap().ops()
+            );
+        }
+
+        #[test]
+        fn undefined_texture() {
+            let program = Program::compile("0 0\n  @mummy texturePixel").unwrap();
+            assert_eq!(
+                Err(Error::UndefinedTexture {
+                    context: Context {
+                        expected: vec![],
+                        op: "@name".into(),
+                        span: Some(Span {
+                            column: 3,
+                            file: 0,
+                            len: 6,
+                            line: 2
+                        }),
+                    },
+                    name: "mummy".into()
+                }),
+                program.bind(&["test".to_string()], &HashMap::new())
+            );
+        }
+
+        #[test]
+        fn undefined_uniform() {
+            let program = Program::compile("1 $threshold").unwrap();
+            assert_eq!(
+                Err(Error::UndefinedUniform {
+                    context: Context {
+                        expected: vec![],
+                        op: "$name".into(),
+                        span: Some(Span {
+                            column: 3,
+                            file: 0,
+                            len: 10,
+                            line: 1
+                        }),
+                    },
+                    name: "threshold".into()
+                }),
+                program.bind(&[], &HashMap::new())
+            );
+        }
+
+        #[test]
+        fn without_spans() {
+            let mut uniforms = HashMap::new();
+            uniforms.insert("threshold".to_string(), Data::F32(0.5));
+
+            let program = Program::new(0, vec![Op::Uniform("threshold".into()), Op::Dup], vec![]);
+            assert_eq!(
+                &[Op::Data(Data::F32(0.5)), Op::Dup],
+                program.unwrap().bind(&[], &uniforms).unwrap().ops()
+            );
+        }
+    }
+
+    mod compile {
+        use super::*;
+
+        #[test]
+        fn empty() {
+            assert_eq!(Ok(Program::default()), Program::compile(""));
+        }
+
+        #[test]
+        fn invalid_token() {
+            assert_eq!(
+                Err(Error::UnhandledToken {
+                    context: Context {
+                        expected: vec![],
+                        op: "garbageDay!!!".into(),
+                        span: Some(Span {
+                            column: 3,
+                            file: 0,
+                            len: 13,
+                            line: 2
+                        }),
+                    },
+                    got: "garbageDay!!!".into()
+                }),
+                Program::compile("1\n  garbageDay!!!")
+            );
+        }
+
+        #[test]
+        fn parses_tokens() {
+            let program = Program::compile("fragPos 0\r\ntexturePixel\tsplitColor").unwrap();
+
+            assert_eq!(
+                &[
+                    Op::FragPos,
+                    Op::Data(Data::U8(0)),
+                    Op::TexturePixel,
+                    Op::SplitColor
+                ],
+                program.ops()
+            );
+            assert_eq!(4, program.spans().len());
+        }
+
+        #[test]
+        fn resolves_words() {
+            let program = Program::compile("1 double : double dup + ; double").unwrap();
+
+            assert_eq!(
+                &[
+                    Op::Dup,
+                    Op::Add,
+                    Op::Return,
+                    Op::Data(Data::U8(1)),
+                    Op::Call(0),
+                    Op::Call(0)
+                ],
+                program.ops()
+            );
+            assert_eq!(3, program.entry());
+            assert_eq!(
+                Some(&Span {
+                    column: 25,
+                    file: 0,
+                    len: 1,
+                    line: 1
+                }),
+                program.spans().get(2)
+            );
+        }
+
+        #[test]
+        fn strips_comments() {
+            let program = Program::compile("# A comment\n1 # dup\n# drop\r\n2").unwrap();
+
+            assert_eq!(
+                &[Op::Data(Data::U8(1)), Op::Data(Data::U8(2))],
+                program.ops()
+            );
+        }
+    }
+
+    mod compile_file {
+        use super::*;
+
+        /// Writes the files to a new directory, returning its path.
+        fn directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+            let directory = std::env::temp_dir().join(format!("das_ubershader_{}", name));
+            for (path, text) in files {
+                let path = directory.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, text).unwrap();
+            }
+
+            directory
+        }
+
+        #[test]
+        fn cycle() {
+            let directory = directory(
+                "include_cycle",
+                &[
+                    ("a.das", "include \"b.das\""),
+                    ("b.das", "1 include \"a.das\""),
+                ],
+            );
+            let mut sources = Sources::new(&directory);
+            let e = Program::compile_file(Path::new("a.das"), "include \"b.das\"", &mut sources)
+                .unwrap_err();
+
+            assert_eq!(
+                Error::InvalidInclude {
+                    context: Context {
+                        expected: vec![],
+                        op: "include".into(),
+                        span: Some(Span {
+                            column: 3,
+                            file: 1,
+                            len: 7,
+                            line: 1
+                        })
+                    },
+                    reason: "`a.das` includes itself through `b.das`".into()
+                },
+                e
+            );
+        }
+
+        #[test]
+        fn error_in_included_file() {
+            let directory = directory("include_error", &[("lib/colors.das", "1\nnope")]);
+            let mut sources = Sources::new(&directory);
+            let e = Program::compile_file(
+                Path::new("main.das"),
+                "include \"lib/colors.das\"",
+                &mut sources,
+            )
+            .unwrap_err();
+
+            let rendered = sources.render(&e);
+            assert!(rendered.contains("--> lib/colors.das:2:1"), "{}", rendered);
+            assert!(rendered.contains("2 | nope"), "{}", rendered);
+        }
+
+        #[test]
+        fn include_once() {
+            let directory = directory(
+                "include_once",
+                &[
+                    ("words.das", ": double 2 * ;"),
+                    ("sub/uses.das", "include \"../words.das\" 3 double"),
+                ],
+            );
+            let mut sources = Sources::new(&directory);
+            let program = Program::compile_file(
+                Path::new("main.das"),
+                "include \"words.das\" include \"sub/uses.das\" double",
+                &mut sources,
+            )
+            .unwrap();
+
+            assert_eq!(
+                &[
+                    Op::Data(Data::U8(2)),
+                    Op::Multiply,
+                    Op::Return,
+                    Op::Data(Data::U8(3)),
+                    Op::Call(0),
+                    Op::Call(0)
+                ],
+                program.ops()
+            );
+            assert_eq!(Path::new("sub/uses.das"), sources.path(2));
+            assert_eq!(2, program.spans()[3].file);
+        }
+
+        #[test]
+        fn missing_file() {
+            let mut sources = Sources::new(&directory("include_missing", &[]));
+            let e =
+                Program::compile_file(Path::new("main.das"), "include \"nope.das\"", &mut sources)
+                    .unwrap_err();
+
+            match e {
+                Error::InvalidInclude { reason, .. } => {
+                    assert!(
+                        reason.starts_with("could not include `nope.das`"),
+                        "{}",
+                        reason
+                    )
+                }
+                e => panic!("expected an invalid include, got {:?}", e),
+            }
+        }
+
+        #[test]
+        fn unquoted_path() {
+            let e = Program::compile("include words.das").unwrap_err();
+
+            assert_eq!("`include` requires a quoted path", e.to_string());
+        }
+    }
+
+    mod hoist {
+        use super::*;
+
+        fn machine() -> PixelMachine {
+            PixelMachine::new(0, 0, 4, 2, vec![]).with_frame(3, 0.1)
+        }
+
+        #[test]
+        fn keeps_failing_prefix() {
+            let program = Program::compile("1 toColor fragPos").unwrap();
+            assert_eq!(program, program.hoist(machine()));
+        }
+
+        #[test]
+        fn precomputes_prefix() {
+            let program = Program::compile(": id ; dim frame id fragPos").unwrap();
+            assert_eq!(
+                &[
+                    Op::Return,
+                    Op::Data(Data::U32(4)),
+                    Op::Data(Data::U32(2)),
+                    Op::Data(Data::U32(3)),
+                    Op::Call(0),
+                    Op::FragPos
+                ],
+                program.hoist(machine()).ops()
+            );
+        }
+
+        #[test]
+        fn stops_at_blocks() {
+            let program = Program::compile("frame if true do fragPos end").unwrap();
+            let hoisted = program.hoist(machine());
+            assert_eq!(Op::Data(Data::U32(3)), hoisted.ops()[0]);
+            assert_eq!(Some(5), hoisted.block(1).map(|block| block.end_idx));
+        }
+    }
+
+    mod is_unfinished {
+        use super::*;
+
+        #[test]
+        fn closed() {
+            assert_eq!(false, is_unfinished("if true do 1 else 2 end : one 1 ;"));
+            assert_eq!(false, is_unfinished("if true end"));
+            assert_eq!(false, is_unfinished("\"if\" # while"));
+        }
+
+        #[test]
+        fn open() {
+            assert_eq!(true, is_unfinished("times 2 do"));
+            assert_eq!(true, is_unfinished("while true do if false do end"));
+            assert_eq!(true, is_unfinished(": one 1"));
+        }
+    }
+
+    mod resolve_blocks {
+        use super::*;
+
+        fn resolve(source: &str) -> Result<Vec<Option<Block>>, Error> {
+            let ops: Vec<Op> = tokenize(0, source)
+                .into_iter()
+                .map(|(_, token)| PixelMachine::parse(token).unwrap())
+                .collect();
+
+            resolve_blocks(&ops, &[])
+        }
+
+        fn unbalanced(missing: &'static str, op: Op) -> Result<Vec<Option<Block>>, Error> {
+            Err(Error::UnbalancedBlock {
+                context: Context::new(&op, None),
+                missing,
+            })
+        }
+
+        #[test]
+        fn do_without_opener() {
+            assert_eq!(unbalanced("if", Op::Do), resolve("true do end"));
+        }
+
+        #[test]
+        fn else_before_do() {
+            assert_eq!(unbalanced("do", Op::Else), resolve("if true else end"));
+        }
+
+        #[test]
+        fn else_outside_if() {
+            assert_eq!(unbalanced("if", Op::Else), resolve("times 2 do else end"));
+            assert_eq!(
+                unbalanced("if", Op::Else),
+                resolve("if true do else else end")
+            );
+            assert_eq!(unbalanced("if", Op::Else), resolve("else"));
+        }
+
+        #[test]
+        fn end_without_opener() {
+            assert_eq!(unbalanced("if", Op::End), resolve("end"));
+        }
+
+        #[test]
+        fn if_else() {
+            let block = Block {
+                do_idx: 2,
+                else_idx: Some(4),
+                end_idx: 6,
+                kind: BlockKind::If,
+                start_idx: 0,
+            };
+
+            assert_eq!(
+                Ok(vec![
+                    Some(block),
+                    None,
+                    Some(block),
+                    None,
+                    Some(block),
+                    None,
+                    Some(block),
+                ]),
+                resolve("if true do 1 else 2 end")
+            );
+        }
+
+        #[test]
+        fn missing_do() {
+            assert_eq!(unbalanced("do", Op::While), resolve("while true end"));
+            assert_eq!(unbalanced("do", Op::Times), resolve("times 4"));
+        }
+
+        #[test]
+        fn missing_end() {
+            assert_eq!(unbalanced("end", Op::If), resolve("if true do 1"));
+        }
+
+        #[test]
+        fn nested() {
+            let outer = Block {
+                do_idx: 2,
+                else_idx: None,
+                end_idx: 9,
+                kind: BlockKind::Times,
+                start_idx: 0,
+            };
+            let inner = Block {
+                do_idx: 5,
+                else_idx: None,
+                end_idx: 8,
+                kind: BlockKind::While,
+                start_idx: 3,
+            };
+
+            assert_eq!(
+                Ok(vec![
+                    Some(outer),
+                    None,
+                    Some(outer),
+                    Some(inner),
+                    None,
+                    Some(inner),
+                    None,
+                    None,
+                    Some(inner),
+                    Some(outer),
+                ]),
+                resolve("times 2 do while false do 1 2 end end")
+            );
+        }
+    }
+
+    mod split_words {
+        use super::*;
+
+        fn context(column: usize, op: &str) -> Context {
+            Context {
+                expected: vec![],
+                op: op.into(),
+                span: Some(Span {
+                    column,
+                    file: 0,
+                    len: op.chars().count(),
+                    line: 1,
+                }),
+            }
+        }
+
+        fn split(source: &str) -> Result<(Tokens<'_>, Vec<Word<'_>>), Error> {
+            split_words(tokenize(0, source))
+        }
+
+        #[test]
+        fn already_an_op() {
+            assert_eq!(
+                Err(Error::InvalidWord {
+                    context: context(3, "dup"),
+                    reason: "is already an op"
+                }),
+                split(": dup ;")
+            );
+        }
+
+        #[test]
+        fn already_defined() {
+            assert_eq!(
+                Err(Error::InvalidWord {
+                    context: context(13, "a"),
+                    reason: "is already defined"
+                }),
+                split(": a 1 ; 2 : a ;")
+            );
+        }
+
+        #[test]
+        fn missing_end() {
+            assert_eq!(
+                Err(Error::UnbalancedBlock {
+                    context: context(1, ":"),
+                    missing: ";"
+                }),
+                split(": a 1 : b 2 ;")
+            );
+        }
+
+        #[test]
+        fn missing_name() {
+            assert_eq!(
+                Err(Error::InvalidWord {
+                    context: context(3, ";"),
+                    reason: "is missing a name"
+                }),
+                split(": ;")
+            );
+        }
+
+        #[test]
+        fn missing_start() {
+            assert_eq!(
+                Err(Error::UnbalancedBlock {
+                    context: context(3, ";"),
+                    missing: ":"
+                }),
+                split("1 ;")
+            );
+        }
+
+        #[test]
+        fn words() {
+            let span = |column| Span {
+                column,
+                file: 0,
+                len: 1,
+                line: 1,
+            };
+
+            assert_eq!(
+                Ok((
+                    vec![(span(1), "1"), (span(11), "a")],
+                    vec![Word {
+                        body: vec![(span(7), "2")],
+                        end: span(9),
+                        name: "a"
+                    }]
+                )),
+                split("1 : a 2 ; a")
+            );
+        }
+    }
+
+    mod tokenize {
+        use super::*;
+
+        fn span(column: usize, len: usize, line: usize) -> Span {
+            Span {
+                column,
+                file: 0,
+                len,
+                line,
+            }
+        }
+
+        #[test]
+        fn comments() {
+            assert_eq!(
+                vec![(span(1, 1, 1), "1"), (span(1, 1, 3), "3")],
+                tokenize(0, "1 # 2\n# 4\r\n3")
+            );
+        }
+
+        #[test]
+        fn empty() {
+            assert_eq!(Vec::<(Span, &str)>::new(), tokenize(0, " \n\t\r\n"));
+        }
+
+        #[test]
+        fn lines_and_columns() {
+            assert_eq!(
+                vec![
+                    (span(1, 7, 1), "fragPos"),
+                    (span(9, 1, 1), "0"),
+                    (span(3, 12, 2), "texturePixel"),
+                ],
+                tokenize(0, "fragPos 0\r\n\t texturePixel")
+            );
+        }
+
+        #[test]
+        fn strings() {
+            assert_eq!(
+                vec![
+                    (span(1, 10, 1), r#""a # \" b""#),
+                    (span(12, 7, 1), "uniform"),
+                    (span(1, 3, 2), r#""c "#),
+                ],
+                tokenize(0, "\"a # \\\" b\" uniform\n\"c ")
+            );
+        }
+
+        #[test]
+        fn unicode_columns() {
+            assert_eq!(
+                vec![(span(1, 2, 1), "éé"), (span(4, 1, 1), "1")],
+                tokenize(0, "éé 1")
+            );
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use super::Color;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+
+/// The furthest a sampled coordinate may be from the texture, in pixels.
+/// Floats this large have no fraction left, and neighbouring pixels can be addressed without overflowing.
+const MAX_COORDINATE: f32 = 1e15;
+
+/// How a texture is read outside of its bounds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Address {
+    /// Returns the border color of the texture.
+    Border,
+    /// Returns the closest pixel on the edge.
+    Clamp,
+    /// Repeats the texture, flipping every other repetition.
+    Mirror,
+    /// Repeats the texture.
+    #[default]
+    Repeat,
+}
+
+impl Address {
+    /// Attempts to parse the given name of an addressing mode.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "border" => Some(Address::Border),
+            "clamp" => Some(Address::Clamp),
+            "mirror" => Some(Address::Mirror),
+            "repeat" => Some(Address::Repeat),
+            _ => None,
+        }
+    }
+
+    /// Maps a coordinate onto a texture of the given size, or `None` for the border.
+    fn resolve(&self, i: i64, size: u32) -> Option<u32> {
+        let size = size as i64;
+        let i = match self {
+            Address::Border => {
+                if i < 0 || i >= size {
+                    return None;
+                }
+
+                i
+            }
+            Address::Clamp => i.clamp(0, size - 1),
+            Address::Mirror => {
+                let i = i.rem_euclid(size * 2);
+                if i < size {
+                    i
+                } else {
+                    size * 2 - 1 - i
+                }
+            }
+            Address::Repeat => i.rem_euclid(size),
+        };
+
+        Some(i as u32)
+    }
+}
+
+/// How a texture is filtered when sampled between pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    /// Catmull-Rom interpolation between the 16 closest pixels.
+    Bicubic,
+    /// Linear interpolation between the 4 closest pixels.
+    Bilinear,
+    /// The closest pixel.
+    Nearest,
+}
+
+/// How an input is resized to the dimensions of a pass.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Fit {
+    /// Scales the image to cover the pass, cropping what is outside of it.
+    #[default]
+    Fill,
+    /// Scales the image to fit inside the pass, centered between transparent bars.
+    Fit,
+    /// Keeps the size of the image.
+    #[serde(alias = "none")]
+    Native,
+    /// Scales the image to the pass, ignoring its aspect ratio.
+    Stretch,
+}
+
+impl Fit {
+    /// Resizes the image to the given dimensions.
+    pub fn apply(
+        &self,
+        dimensions: (u32, u32),
+        image: DynamicImage,
+        resample: Resample,
+    ) -> DynamicImage {
+        let (width, height) = dimensions;
+        let filter = resample.into();
+        match self {
+            Fit::Fill => image.resize_to_fill(width, height, filter),
+            Fit::Fit => {
+                let resized = image.resize(width, height, filter);
+                let x = (width - resized.width()) / 2;
+                let y = (height - resized.height()) / 2;
+
+                let mut letterboxed = DynamicImage::new_rgba8(width, height);
+                image::imageops::overlay(&mut letterboxed, &resized, x.into(), y.into());
+                letterboxed
+            }
+            Fit::Native => image,
+            Fit::Stretch => image.resize_exact(width, height, filter),
+        }
+    }
+}
+
+/// The filter used when resizing an input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Resample {
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+    #[default]
+    Nearest,
+    Triangle,
+}
+
+impl From<Resample> for FilterType {
+    fn from(resample: Resample) -> Self {
+        match resample {
+            Resample::CatmullRom => FilterType::CatmullRom,
+            Resample::Gaussian => FilterType::Gaussian,
+            Resample::Lanczos3 => FilterType::Lanczos3,
+            Resample::Nearest => FilterType::Nearest,
+            Resample::Triangle => FilterType::Triangle,
+        }
+    }
+}
+
+/// Interpolates between the middle two points using a Catmull-Rom spline.
+fn cubic(points: [[f32; 4]; 4], t: f32) -> [f32; 4] {
+    let [p0, p1, p2, p3] = points;
+
+    let mut interpolated = [0.0; 4];
+    for (c, value) in interpolated.iter_mut().enumerate() {
+        let a = -0.5 * p0[c] + 1.5 * p1[c] - 1.5 * p2[c] + 0.5 * p3[c];
+        let b = p0[c] - 2.5 * p1[c] + 2.0 * p2[c] - 0.5 * p3[c];
+        let d = -0.5 * p0[c] + 0.5 * p2[c];
+
+        *value = ((a * t + b) * t + d) * t + p1[c];
+    }
+
+    interpolated
+}
+
+/// Linearly interpolates between two pixels.
+fn lerp(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    let mut interpolated = a;
+    for (value, b) in interpolated.iter_mut().zip(b.iter()) {
+        *value += (b - *value) * t;
+    }
+
+    interpolated
+}
+
+/// An input image and how it is read outside of its bounds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Texture {
+    /// The default addressing mode.
+    pub address: Address,
+    /// The color outside of the texture when using `Address::Border`.
+    pub border: Color,
+    pub image: DynamicImage,
+    /// The name the texture is looked up by.
+    pub name: Option<String>,
+}
+
+impl Texture {
+    /// Creates a new unnamed texture that repeats with a transparent border.
+    pub fn new(image: DynamicImage) -> Self {
+        Self {
+            address: Address::default(),
+            border: (0, 0, 0, 0).into(),
+            image,
+            name: None,
+        }
+    }
+
+    /// Returns the pixel at the given position.
+    pub fn pixel(&self, address: Address, x: i64, y: i64) -> Color {
+        let [r, g, b, a] = self.texel(address, x, y).map(|c| c as u8);
+        Color { r, g, b, a }
+    }
+
+    /// Samples the texture at the given normalized coordinates.
+    pub fn sample(&self, address: Address, filter: Filter, uv: [f32; 2]) -> Color {
+        let (width, height) = self.image.dimensions();
+        let x = (uv[0] * width as f32).clamp(-MAX_COORDINATE, MAX_COORDINATE);
+        let y = (uv[1] * height as f32).clamp(-MAX_COORDINATE, MAX_COORDINATE);
+
+        let rgba = match filter {
+            Filter::Bicubic => {
+                // Pixel centers lie on the half pixel.
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (tx, ty) = (x - x.floor(), y - y.floor());
+                let (x, y) = (x.floor() as i64, y.floor() as i64);
+
+                let mut rows = [[0.0; 4]; 4];
+                for (row, j) in rows.iter_mut().zip(-1..=2) {
+                    let mut points = [[0.0; 4]; 4];
+                    for (point, i) in points.iter_mut().zip(-1..=2) {
+                        *point = self.texel(address, x + i, y + j);
+                    }
+
+                    *row = cubic(points, tx);
+                }
+
+                cubic(rows, ty)
+            }
+            Filter::Bilinear => {
+                // Pixel centers lie on the half pixel.
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (tx, ty) = (x - x.floor(), y - y.floor());
+                let (x, y) = (x.floor() as i64, y.floor() as i64);
+
+                let top = lerp(self.texel(address, x, y), self.texel(address, x + 1, y), tx);
+                let bottom = lerp(
+                    self.texel(address, x, y + 1),
+                    self.texel(address, x + 1, y + 1),
+                    tx,
+                );
+
+                lerp(top, bottom, ty)
+            }
+            Filter::Nearest => self.texel(address, x.floor() as i64, y.floor() as i64),
+        };
+
+        let [r, g, b, a] = rgba.map(|c| c.clamp(0.0, 255.0).round() as u8);
+        Color { r, g, b, a }
+    }
+
+    /// Returns the channels of the pixel at the given position.
+    fn texel(&self, address: Address, x: i64, y: i64) -> [f32; 4] {
+        let (width, height) = self.image.dimensions();
+        let position = if width == 0 || height == 0 {
+            None
+        } else {
+            address.resolve(x, width).zip(address.resolve(y, height))
+        };
+
+        match position {
+            Some((x, y)) => self.image.get_pixel(x, y).0.map(|c| c as f32),
+            None => {
+                let Color { r, g, b, a } = self.border;
+                [r, g, b, a].map(|c| c as f32)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4x1 texture going from black to white.
+    fn gradient() -> Texture {
+        Texture::new(DynamicImage::ImageRgba8(image::RgbaImage::from_fn(
+            4,
+            1,
+            |x, _| {
+                let c = (x * 85) as u8;
+                image::Rgba([c, c, c, 255])
+            },
+        )))
+    }
+
+    fn gray(c: u8) -> Color {
+        (c, c, c, 255).into()
+    }
+
+    mod apply {
+        use super::*;
+
+        /// A 4x2 opaque image.
+        fn wide() -> DynamicImage {
+            DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                4,
+                2,
+                image::Rgba([255, 0, 0, 255]),
+            ))
+        }
+
+        #[test]
+        fn fill() {
+            let image = Fit::Fill.apply((2, 2), wide(), Resample::Nearest);
+            assert_eq!((2, 2), image.dimensions());
+            assert_eq!(image::Rgba([255, 0, 0, 255]), image.get_pixel(0, 0));
+        }
+
+        #[test]
+        fn fit() {
+            let image = Fit::Fit.apply((4, 4), wide(), Resample::Triangle);
+            assert_eq!((4, 4), image.dimensions());
+            assert_eq!(image::Rgba([0, 0, 0, 0]), image.get_pixel(0, 0));
+            assert_eq!(image::Rgba([255, 0, 0, 255]), image.get_pixel(0, 1));
+            assert_eq!(image::Rgba([0, 0, 0, 0]), image.get_pixel(0, 3));
+        }
+
+        #[test]
+        fn native() {
+            let image = Fit::Native.apply((8, 8), wide(), Resample::Lanczos3);
+            assert_eq!((4, 2), image.dimensions());
+        }
+
+        #[test]
+        fn stretch() {
+            let image = Fit::Stretch.apply((3, 5), wide(), Resample::CatmullRom);
+            assert_eq!((3, 5), image.dimensions());
+        }
+    }
+
+    mod pixel {
+        use super::*;
+
+        #[test]
+        fn border() {
+            let texture = Texture {
+                border: (1, 2, 3, 4).into(),
+                ..gradient()
+            };
+
+            let expected: Color = (1, 2, 3, 4).into();
+            assert_eq!(expected, texture.pixel(Address::Border, 4, 0));
+            assert_eq!(gray(85), texture.pixel(Address::Border, 1, 0));
+        }
+
+        #[test]
+        fn clamp() {
+            assert_eq!(gray(0), gradient().pixel(Address::Clamp, -3, 0));
+            assert_eq!(gray(255), gradient().pixel(Address::Clamp, 9, 5));
+        }
+
+        #[test]
+        fn mirror() {
+            assert_eq!(gray(255), gradient().pixel(Address::Mirror, 4, 0));
+            assert_eq!(gray(170), gradient().pixel(Address::Mirror, 5, 0));
+            assert_eq!(gray(0), gradient().pixel(Address::Mirror, -1, 0));
+        }
+
+        #[test]
+        fn repeat() {
+            assert_eq!(gray(0), gradient().pixel(Address::Repeat, 4, 0));
+            assert_eq!(gray(255), gradient().pixel(Address::Repeat, -1, 0));
+        }
+    }
+
+    mod sample {
+        use super::*;
+
+        #[test]
+        fn bicubic_pixel_center() {
+            assert_eq!(
+                gray(85),
+                gradient().sample(Address::Repeat, Filter::Bicubic, [0.375, 0.5])
+            );
+        }
+
+        #[test]
+        fn bicubic_between_pixels() {
+            assert_eq!(
+                gray(128),
+                gradient().sample(Address::Repeat, Filter::Bicubic, [0.5, 0.5])
+            );
+        }
+
+        #[test]
+        fn bilinear_between_pixels() {
+            assert_eq!(
+                gray(128),
+                gradient().sample(Address::Repeat, Filter::Bilinear, [0.5, 0.5])
+            );
+        }
+
+        #[test]
+        fn bilinear_clamps() {
+            assert_eq!(
+                gray(255),
+                gradient().sample(Address::Clamp, Filter::Bilinear, [1.0, 0.5])
+            );
+        }
+
+        #[test]
+        fn bilinear_wraps() {
+            assert_eq!(
+                gray(128),
+                gradient().sample(Address::Repeat, Filter::Bilinear, [1.0, 0.5])
+            );
+        }
+
+        #[test]
+        fn far_coordinates() {
+            let filters = [Filter::Bicubic, Filter::Bilinear, Filter::Nearest];
+            for filter in filters {
+                for u in [1e30, f32::INFINITY] {
+                    assert_eq!(
+                        gray(255),
+                        gradient().sample(Address::Clamp, filter, [u, 0.5])
+                    );
+                    assert_eq!(
+                        gray(0),
+                        gradient().sample(Address::Clamp, filter, [-u, 0.5])
+                    );
+                    assert_eq!(
+                        Color {
+                            r: 0,
+                            g: 0,
+                            b: 0,
+                            a: 0
+                        },
+                        gradient().sample(Address::Border, filter, [u, 0.5])
+                    );
+                    gradient().sample(Address::Mirror, filter, [u, -u]);
+                    gradient().sample(Address::Repeat, filter, [-u, u]);
+                }
+            }
+        }
+
+        #[test]
+        fn nearest() {
+            assert_eq!(
+                gray(170),
+                gradient().sample(Address::Repeat, Filter::Nearest, [0.6, 0.5])
+            );
+        }
+
+        #[test]
+        fn nearest_wraps() {
+            assert_eq!(
+                gray(255),
+                gradient().sample(Address::Repeat, Filter::Nearest, [-0.1, 2.5])
+            );
+        }
+    }
+}
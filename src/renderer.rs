@@ -0,0 +1,470 @@
+use crate::output;
+use crate::pixel_machine::{self, Color, Fit, PixelMachine, Program, Resample, Sources, Texture};
+use crate::{bytecode, repl, Cfg, Data, Input, Pass, RenderError};
+use image::ImageError;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Loads the files read by a pass, resized to the pass. Inputs of earlier passes are `None`.
+fn load_files(
+    dimensions: (u32, u32),
+    directory: &Path,
+    inputs: &[Input],
+) -> Result<Vec<Option<Arc<Texture>>>, RenderError> {
+    let open = |path: &str| {
+        let path = directory.join(path);
+        image::open(&path).map_err(|source| match source {
+            ImageError::IoError(source) => RenderError::Io { path, source },
+            source => RenderError::Decode { path, source },
+        })
+    };
+
+    inputs
+        .par_iter()
+        .map(|input| match input {
+            Input::Path(path) => {
+                let image = open(path)?;
+                let image = Fit::default().apply(dimensions, image, Resample::default());
+
+                Ok(Some(Arc::new(input.texture(image))))
+            }
+            Input::Texture {
+                fit,
+                path,
+                resample,
+                ..
+            } => {
+                let image = open(path)?;
+                let image = fit.apply(dimensions, image, *resample);
+
+                Ok(Some(Arc::new(input.texture(image))))
+            }
+            Input::Pass { .. } => Ok(None),
+        })
+        .collect()
+}
+
+/// Runs the program over every pixel, returning an image for each render target.
+pub(crate) fn render_pass(
+    frame: u32,
+    stage: &Stage,
+    textures: Vec<Arc<Texture>>,
+    time: f32,
+) -> Result<Vec<image::RgbaImage>, pixel_machine::Error> {
+    let (width, height) = stage.dimensions;
+    let targets = stage.pass.targets();
+
+    // Run the ops that are the same for every pixel once
+    let program = stage
+        .program
+        .hoist(PixelMachine::new(0, 0, width, height, textures.clone()).with_frame(frame, time));
+
+    // Build up pixels
+    let pixels = {
+        let mut pixels = vec![];
+        for x in 0..width {
+            for y in 0..height {
+                pixels.push((x, y));
+            }
+        }
+        pixels
+    };
+
+    // Process pixels
+    let new_pixels: Vec<(u32, u32, Result<Vec<Color>, pixel_machine::Error>)> = pixels
+        .par_iter()
+        .map(|(x, y)| {
+            let colors = PixelMachine::new(*x, *y, width, height, textures.clone())
+                .with_frame(frame, time)
+                .run_outputs(targets, &program);
+
+            (*x, *y, colors)
+        })
+        .collect();
+
+    // Write calculated pixels to images
+    let mut new_images: Vec<image::RgbaImage> = (0..targets)
+        .map(|_| image::ImageBuffer::new(width, height))
+        .collect();
+
+    for (x, y, colors) in new_pixels {
+        for (new_image, color) in new_images.iter_mut().zip(colors?) {
+            *new_image.get_pixel_mut(x, y) =
+                image::Rgba::<u8>([color.r, color.g, color.b, color.a]);
+        }
+    }
+
+    Ok(new_images)
+}
+
+/// Renders the first frame of the given passes, returning their render targets by pass name for later passes to read.
+pub(crate) fn render_passes(
+    stages: &[Stage],
+) -> Result<HashMap<&str, Vec<image::DynamicImage>>, RenderError> {
+    let mut rendered = HashMap::new();
+    for stage in stages {
+        let images = render_pass(0, stage, stage.textures(&rendered), 0.0)
+            .map_err(|e| stage.program_error(e))?;
+        rendered.insert(
+            stage.pass.name.as_str(),
+            images
+                .into_iter()
+                .map(image::DynamicImage::ImageRgba8)
+                .collect(),
+        );
+    }
+
+    Ok(rendered)
+}
+
+/// Renders the passes of a config, with every program compiled and file loaded once for all frames.
+pub struct Renderer {
+    /// The directory outputs are written to.
+    directory: PathBuf,
+    fps: f32,
+    frames: u32,
+    stages: Vec<Stage>,
+    uniforms: HashMap<String, Data>,
+}
+
+impl Renderer {
+    /// Returns the ops of every pass before and after optimizing.
+    pub fn dump_ops(&self) -> String {
+        self.stages
+            .iter()
+            .map(|stage| {
+                format!(
+                    "pass {}: {} ops before optimizing, {} after\nbefore:\n{}after:\n{}",
+                    stage.pass.name,
+                    stage.unoptimized.ops().len(),
+                    stage.program.ops().len(),
+                    stage.unoptimized.listing(),
+                    stage.program.listing()
+                )
+            })
+            .collect()
+    }
+
+    /// Compiles the passes of the config and loads their files, reading uniforms from the given values.
+    pub fn new(cfg: &Cfg, uniforms: &HashMap<String, Data>) -> Result<Self, RenderError> {
+        let config_error = |reason: String| RenderError::Config {
+            path: cfg.path.clone(),
+            reason,
+        };
+        let directory = cfg.directory().to_path_buf();
+
+        let fps = cfg.fps();
+        if !fps.is_finite() || fps <= 0.0 {
+            return Err(config_error(format!(
+                "fps must be a positive number, got {}",
+                fps
+            )));
+        }
+
+        let passes = cfg
+            .passes()
+            .ok_or_else(|| config_error("required a program or passes".into()))?;
+        let mut stages: Vec<Stage> = vec![];
+        for pass in passes {
+            let dimensions = (
+                pass.width.unwrap_or(cfg.width),
+                pass.height.unwrap_or(cfg.height),
+            );
+            if stages.iter().any(|stage| stage.pass.name == pass.name) {
+                return Err(config_error(format!(
+                    "more than one pass is named {}",
+                    pass.name
+                )));
+            }
+            if pass.targets() == 0 {
+                return Err(config_error(format!(
+                    "pass {} must render at least one target",
+                    pass.name
+                )));
+            }
+            if pass.outputs.len() > pass.targets() {
+                return Err(config_error(format!(
+                    "pass {} has {} outputs, but only {} targets",
+                    pass.name,
+                    pass.outputs.len(),
+                    pass.targets()
+                )));
+            }
+            for output in &pass.outputs {
+                if let Some(Err(e)) = output.format().map(output::Format::validate) {
+                    return Err(config_error(e.to_string()));
+                }
+            }
+
+            if let Some(name) = pass.inputs.duplicate() {
+                return Err(config_error(format!(
+                    "pass {} has more than one input named {}",
+                    pass.name, name
+                )));
+            }
+            let inputs = pass.inputs.to_vec();
+            for input in &inputs {
+                if let Input::Pass {
+                    pass: earlier,
+                    target,
+                    ..
+                } = input
+                {
+                    match stages.iter().find(|stage| stage.pass.name == *earlier) {
+                        Some(stage) if *target >= stage.pass.targets() => {
+                            return Err(config_error(format!(
+                                "pass {} reads target {} of pass {}, which has {} targets",
+                                pass.name,
+                                target,
+                                earlier,
+                                stage.pass.targets()
+                            )))
+                        }
+                        Some(_) => {}
+                        None => {
+                            return Err(config_error(format!(
+                                "pass {} reads pass {}, which is not rendered before it",
+                                pass.name, earlier
+                            )))
+                        }
+                    }
+                }
+            }
+
+            // Programs are either source code or compiled with the compile subcommand
+            let program_file = directory.join(&pass.program);
+            let bytes = std::fs::read(&program_file).map_err(|source| RenderError::Io {
+                path: program_file.clone(),
+                source,
+            })?;
+            let names: Vec<String> = inputs.iter().map(|input| input.name()).collect();
+            let mut sources = Sources::new(&directory);
+            let program = if bytecode::is_bytecode(&bytes) {
+                let program = bytecode::decode(&bytes, Path::new(&pass.program), &mut sources)
+                    .map_err(|source| RenderError::Bytecode {
+                        path: program_file,
+                        source,
+                    })?;
+                Ok(program)
+            } else {
+                let source = String::from_utf8(bytes).map_err(|e| RenderError::Io {
+                    path: program_file,
+                    source: io::Error::new(io::ErrorKind::InvalidData, e),
+                })?;
+                Program::compile_file(Path::new(&pass.program), &source, &mut sources)
+            };
+            let program = program.and_then(|program| {
+                let program = program.bind(&names, uniforms)?;
+                program.check(pass.targets())?;
+                Ok(program)
+            });
+            let program = match program {
+                Ok(program) => program,
+                Err(e) => {
+                    return Err(RenderError::Program {
+                        source: Box::new(e),
+                        sources,
+                    })
+                }
+            };
+
+            let files = load_files(dimensions, &directory, &inputs)?;
+            stages.push(Stage {
+                dimensions,
+                files,
+                inputs,
+                pass,
+                program: Arc::new(program.optimize()),
+                sources,
+                unoptimized: Arc::new(program),
+            });
+        }
+
+        Ok(Self {
+            directory,
+            fps,
+            frames: cfg.frames(),
+            stages,
+            uniforms: uniforms.clone(),
+        })
+    }
+
+    /// Renders every pass of a frame, returning the images of each render target for each pass.
+    pub fn render_frame(&self, frame: u32) -> Result<Vec<Vec<image::RgbaImage>>, RenderError> {
+        let time = frame as f32 / self.fps;
+        let mut rendered: HashMap<&str, Vec<image::DynamicImage>> = HashMap::new();
+        let mut targets = vec![];
+        for stage in &self.stages {
+            let textures = stage.textures(&rendered);
+            let images =
+                render_pass(frame, stage, textures, time).map_err(|e| stage.program_error(e))?;
+            rendered.insert(
+                &stage.pass.name,
+                images
+                    .iter()
+                    .cloned()
+                    .map(image::DynamicImage::ImageRgba8)
+                    .collect(),
+            );
+            targets.push(images);
+        }
+
+        Ok(targets)
+    }
+
+    /// Starts the REPL at a pixel of the last pass, reading lines from `input` until `:quit` and writing to `output`.
+    pub fn repl(
+        &self,
+        input: impl io::BufRead,
+        output: &mut impl io::Write,
+    ) -> Result<(), RenderError> {
+        repl::run(input, output, &self.stages, &self.uniforms)
+    }
+
+    /// Renders every frame in parallel, writing the render targets of every pass to their outputs as soon as they are rendered.
+    /// Only the frames of animated GIFs are kept until every frame is rendered, since they are encoded at once.
+    pub fn save(&self) -> Result<(), RenderError> {
+        let animated = self.frames > 1;
+        let gif = |output: &output::Output| animated && output.is_gif();
+
+        let frames = (0..self.frames)
+            .into_par_iter()
+            .map(|frame| {
+                let mut gifs = vec![];
+                for (images, stage) in self.render_frame(frame)?.into_iter().zip(&self.stages) {
+                    for (image, output) in images.into_iter().zip(&stage.pass.outputs) {
+                        if gif(output) {
+                            gifs.push(image);
+                        } else {
+                            let path = self.directory.join(output.path());
+                            output::save(Some(frame).filter(|_| animated), &image, output, &path)?;
+                        }
+                    }
+                }
+
+                Ok(gifs)
+            })
+            .collect::<Result<Vec<_>, RenderError>>()?;
+
+        let outputs: Vec<&output::Output> = self
+            .stages
+            .iter()
+            .flat_map(|stage| &stage.pass.outputs)
+            .filter(|output| gif(output))
+            .collect();
+        let mut animations = vec![vec![]; outputs.len()];
+        for gifs in frames {
+            for (animation, image) in animations.iter_mut().zip(gifs) {
+                animation.push(image);
+            }
+        }
+
+        for (output, images) in outputs.into_iter().zip(animations) {
+            output::save_gif(self.fps, images, &self.directory.join(output.path()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns a line for every op executed for a single pixel of the last pass in the first frame.
+    /// Earlier passes are rendered once so the last pass can read them.
+    pub fn trace(&self, position: (u32, u32)) -> Result<Vec<String>, RenderError> {
+        let (stage, earlier) = match self.stages.split_last() {
+            Some(stages) => stages,
+            None => return Ok(vec![]),
+        };
+
+        let (x, y) = position;
+        let (width, height) = stage.dimensions;
+        if x >= width || y >= height {
+            return Err(RenderError::Usage {
+                reason: format!(
+                    "pixel {},{} is outside of pass {}, which is {}x{}",
+                    x, y, stage.pass.name, width, height
+                ),
+            });
+        }
+
+        let rendered = render_passes(earlier)?;
+        let textures = stage.textures(&rendered);
+
+        let mut lines = vec![format!("pass {} at {},{}", stage.pass.name, x, y)];
+        let colors = PixelMachine::new(x, y, width, height, textures)
+            .trace(stage.pass.targets(), &stage.unoptimized, |step| {
+                let token = match step.span {
+                    Some(span) => stage
+                        .sources
+                        .text(span.file)
+                        .lines()
+                        .nth(span.line.saturating_sub(1))
+                        .unwrap_or_default()
+                        .chars()
+                        .skip(span.column.saturating_sub(1))
+                        .take(span.len)
+                        .collect(),
+                    None => step.op.name().to_string(),
+                };
+                lines.push(format!(
+                    "{:>6} {:<16} {:?}",
+                    step.instruction_pointer, token, step.stack
+                ));
+            })
+            .map_err(|e| stage.program_error(e))?;
+        for (target, color) in colors.iter().enumerate() {
+            lines.push(format!(
+                "target {} = {},{},{},{}",
+                target, color.r, color.g, color.b, color.a
+            ));
+        }
+
+        Ok(lines)
+    }
+}
+
+/// A pass with its program compiled and its files loaded, ready to render any frame.
+pub(crate) struct Stage {
+    pub(crate) dimensions: (u32, u32),
+    /// The loaded files, lined up with the inputs of the pass.
+    pub(crate) files: Vec<Option<Arc<Texture>>>,
+    /// The inputs of the pass in the order of their indices.
+    pub(crate) inputs: Vec<Input>,
+    pub(crate) pass: Pass,
+    /// The optimized program that renders the pass.
+    pub(crate) program: Arc<Program>,
+    /// The files the program was compiled from.
+    pub(crate) sources: Sources,
+    /// The program as it was written, which traces every op.
+    pub(crate) unoptimized: Arc<Program>,
+}
+
+impl Stage {
+    /// Wraps an error of the program, pointing at the source of the pass.
+    pub(crate) fn program_error(&self, e: pixel_machine::Error) -> RenderError {
+        RenderError::Program {
+            source: Box::new(e),
+            sources: self.sources.clone(),
+        }
+    }
+
+    /// Returns the textures of the pass, reading earlier passes from their rendered targets.
+    pub(crate) fn textures(
+        &self,
+        rendered: &HashMap<&str, Vec<image::DynamicImage>>,
+    ) -> Vec<Arc<Texture>> {
+        self.inputs
+            .iter()
+            .zip(self.files.iter())
+            .map(|(input, file)| match (input, file) {
+                (_, Some(texture)) => texture.clone(),
+                (Input::Pass { pass, target, .. }, None) => {
+                    let image = rendered[pass.as_str()][*target].clone();
+                    Arc::new(input.texture(image))
+                }
+                (_, None) => unreachable!("files are loaded for every path"),
+            })
+            .collect()
+    }
+}
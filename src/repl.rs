@@ -0,0 +1,271 @@
+use crate::pixel_machine::{is_unfinished, Data, PixelMachine, Program, Sources};
+use crate::renderer::{render_passes, Stage};
+use crate::RenderError;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// The commands listed by `:help`.
+const HELP: &str = "\
+Enter tokens to execute them, printing the stack after each line.
+Words stay defined for the session, and unfinished words and blocks continue on the next line.
+An empty line ends unfinished input.
+:help          Shows this message.
+:pixel x,y     Moves to another pixel, keeping the stack.
+:quit          Exits the REPL.
+:reset         Clears the stack and unfinished input.
+:signature op  Shows the inputs and outputs of an op.";
+
+/// A line starting with `:` that controls the REPL instead of executing tokens.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Help,
+    Pixel((u32, u32)),
+    Quit,
+    Reset,
+    Signature(String),
+}
+
+impl Command {
+    /// Parses a command without its leading `:`, returning why it is invalid on failure.
+    fn parse(command: &str) -> Result<Self, String> {
+        let (name, argument) = match command.trim().split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (command.trim(), ""),
+        };
+
+        match (name, argument) {
+            ("help", "") => Ok(Command::Help),
+            ("pixel", position) => position
+                .split_once(',')
+                .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
+                .map(Command::Pixel)
+                .ok_or_else(|| "expected :pixel x,y".to_string()),
+            ("quit", "") => Ok(Command::Quit),
+            ("reset", "") => Ok(Command::Reset),
+            ("signature", "") => Err("expected :signature op".into()),
+            ("signature", op) => Ok(Command::Signature(op.into())),
+            _ => Err(format!("unknown command :{}, see :help", command.trim())),
+        }
+    }
+}
+
+/// Runs the REPL at a pixel of the last pass with its textures loaded, reading lines from `input` until `:quit`.
+/// Earlier passes are rendered once for the first frame so the last pass can read them.
+/// Words stay defined for the whole session, and lines that leave a word or block open are continued by the next line.
+pub(crate) fn run(
+    input: impl BufRead,
+    output: &mut impl Write,
+    stages: &[Stage],
+    uniforms: &HashMap<String, Data>,
+) -> Result<(), RenderError> {
+    let (stage, earlier) = match stages.split_last() {
+        Some(stages) => stages,
+        None => return Ok(()),
+    };
+
+    let rendered = render_passes(earlier)?;
+    let (width, height) = stage.dimensions;
+    let names: Vec<String> = stage.inputs.iter().map(|input| input.name()).collect();
+    let textures = stage.textures(&rendered);
+    let machine_at = |(x, y): (u32, u32)| PixelMachine::new(x, y, width, height, textures.clone());
+    let write_error = |source| RenderError::Io {
+        path: "stdout".into(),
+        source,
+    };
+
+    writeln!(
+        output,
+        "REPL for pass {} ({}x{}), enter :help for commands",
+        stage.pass.name, width, height
+    )
+    .map_err(write_error)?;
+    let mut position = (0, 0);
+    let mut machine = machine_at(position);
+    let mut lines = input.lines();
+    // The main ops of the session that were already executed, which are skipped when recompiling it.
+    let mut executed = 0;
+    let mut pending = String::new();
+    let mut session = String::new();
+    loop {
+        if pending.is_empty() {
+            write!(output, "{},{}> ", position.0, position.1).map_err(write_error)?;
+        } else {
+            write!(output, "...> ").map_err(write_error)?;
+        }
+        output.flush().map_err(write_error)?;
+        let line = match lines.next().transpose() {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(source) => {
+                return Err(RenderError::Io {
+                    path: "stdin".into(),
+                    source,
+                })
+            }
+        };
+
+        let line = line.trim();
+        let command = line
+            .strip_prefix(':')
+            .filter(|command| command.starts_with(|c: char| !c.is_whitespace()));
+        if let Some(command) = command {
+            match Command::parse(command) {
+                Ok(Command::Help) => writeln!(output, "{}", HELP),
+                Ok(Command::Pixel((x, y))) if x >= width || y >= height => writeln!(
+                    output,
+                    "error: pixel {},{} is outside of {}x{}",
+                    x, y, width, height
+                ),
+                Ok(Command::Pixel(pixel)) => {
+                    let stack = machine.stack_values();
+                    position = pixel;
+                    machine = machine_at(position);
+                    for data in stack {
+                        machine.push(data).ok();
+                    }
+                    Ok(())
+                }
+                Ok(Command::Quit) => break,
+                Ok(Command::Reset) => {
+                    machine = machine_at(position);
+                    pending.clear();
+                    Ok(())
+                }
+                Ok(Command::Signature(token)) => match PixelMachine::parse(&token) {
+                    Ok(op) => writeln!(output, "{}", op.signature()),
+                    Err(e) => writeln!(output, "error: {}", e),
+                },
+                Err(reason) => writeln!(output, "error: {}", reason),
+            }
+            .map_err(write_error)?;
+
+            continue;
+        }
+
+        // An empty line ends unfinished input so the error of what is left open is shown.
+        if line.is_empty() && pending.is_empty() {
+            continue;
+        }
+        if !line.is_empty() {
+            pending.push_str(line);
+            pending.push('\n');
+            if is_unfinished(&pending) {
+                continue;
+            }
+        }
+
+        let source = format!("{}{}", session, pending);
+        pending.clear();
+        let mut sources = Sources::default();
+        let result =
+            Program::compile_file(Path::new("repl"), &source, &mut sources).and_then(|program| {
+                let unexecuted = Program::new(
+                    program.entry() + executed,
+                    program.ops().to_vec(),
+                    program.spans().to_vec(),
+                )?
+                .bind(&names, uniforms)?;
+                executed = program.ops().len() - program.entry();
+                session = source;
+                machine.execute_program(&unexecuted)
+            });
+        if let Err(e) = result {
+            write!(output, "{}", sources.render(&e)).map_err(write_error)?;
+        }
+
+        writeln!(output, "{:?}", machine.stack_values()).map_err(write_error)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod parse {
+        use super::*;
+
+        #[test]
+        fn commands() {
+            assert_eq!(Ok(Command::Help), Command::parse("help"));
+            assert_eq!(Ok(Command::Pixel((3, 4))), Command::parse("pixel 3, 4"));
+            assert_eq!(Ok(Command::Quit), Command::parse(" quit "));
+            assert_eq!(Ok(Command::Reset), Command::parse("reset"));
+            assert_eq!(
+                Ok(Command::Signature("rotN".into())),
+                Command::parse("signature rotN")
+            );
+        }
+
+        #[test]
+        fn invalid() {
+            assert_eq!(Err("expected :pixel x,y".into()), Command::parse("pixel 3"));
+            assert_eq!(
+                Err("expected :signature op".into()),
+                Command::parse("signature")
+            );
+            assert_eq!(
+                Err("unknown command :jump, see :help".into()),
+                Command::parse("jump")
+            );
+        }
+    }
+
+    mod run {
+        use super::*;
+        use crate::{Cfg, Renderer};
+
+        /// Runs the REPL on the last pass of example3 with the given lines, returning what it wrote.
+        fn script(lines: &str) -> String {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("example3/cfg.json");
+            let cfg = Cfg::load(&path).unwrap();
+            let renderer = Renderer::new(&cfg, &cfg.uniforms()).unwrap();
+            let mut output = vec![];
+            renderer
+                .repl(std::io::Cursor::new(lines), &mut output)
+                .unwrap();
+
+            String::from_utf8(output).unwrap()
+        }
+
+        #[test]
+        fn keeps_words_and_continues_blocks() {
+            assert_eq!(
+                "REPL for pass main (320x240), enter :help for commands
+0,0> []
+0,0> [U32(9)]
+0,0> ...> ...> [U32(6561)]
+0,0> ...> error: `if` is missing a matching `end`
+ --> repl:6:1
+  |
+6 | if true do
+  | ^^
+  = note: `if = [cond:bool] -> []`
+[U32(6561)]
+0,0> [U32(6561), U8(1)]
+0,0> ",
+                script(": sq dup * ;\n3 sq\ntimes 2 do\nsq\nend\nif true do\n\n1\n")
+            );
+        }
+
+        #[test]
+        fn prints_stack_and_recovers() {
+            assert_eq!(
+                "REPL for pass main (320x240), enter :help for commands
+0,0> [U8(1), U8(2)]
+0,0> error: `+` expected [Number, Number], got Bool(true)
+ --> repl:2:6
+  |
+2 | true +
+  |      ^
+  = note: `+ = [a:Number b:Number] -> [Number]`
+[U8(1)]
+0,0> [U32(4)]
+0,0> ",
+                script("1 2\ntrue +\n3 +\n:quit\n4\n")
+            );
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use das_ubershader::{Color, Data, Error, Op, PixelMachine, Program, Type};
+
+fn machine() -> PixelMachine {
+    PixelMachine::new(3, 4, 8, 8, vec![])
+}
+
+mod execute {
+    use super::*;
+
+    #[test]
+    fn parsed_ops() {
+        let mut machine = machine();
+        for token in ["1", "2", "+"] {
+            machine
+                .execute(PixelMachine::parse(token).unwrap())
+                .unwrap();
+        }
+
+        assert_eq!(vec![Data::U32(3)], machine.stack_values());
+    }
+
+    #[test]
+    fn underflow() {
+        let result = machine().execute(Op::Add);
+
+        match result {
+            Err(Error::StackUnderflow { context }) => assert_eq!("+", context.op),
+            result => panic!("expected a stack underflow, got {:?}", result),
+        }
+    }
+}
+
+mod run {
+    use super::*;
+
+    #[test]
+    fn color() {
+        let program = Program::compile("fragPos + 0 0 255 makeColor").unwrap();
+
+        assert_eq!(
+            Ok(Color {
+                r: 7,
+                g: 0,
+                b: 0,
+                a: 255
+            }),
+            machine().run(&program)
+        );
+    }
+
+    #[test]
+    fn checked_types() {
+        let program = Program::compile("1 2").unwrap();
+
+        match program.check(1) {
+            Err(Error::InvalidResult { got, .. }) => assert_eq!(Type::U8, got),
+            result => panic!("expected an invalid result, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use das_ubershader::output::Output;
+use das_ubershader::{bytecode, compile, render, Cfg, RenderError, Renderer};
+use std::path::{Path, PathBuf};
+
+/// Returns the error of a result that must have failed with an error `is_expected` accepts.
+fn expect_err<T>(
+    is_expected: fn(&RenderError) -> bool,
+    result: Result<T, RenderError>,
+) -> RenderError {
+    match result {
+        Err(e) if is_expected(&e) => e,
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("expected an error"),
+    }
+}
+
+/// Returns the path of a file in the repository.
+fn file(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+mod compile {
+    use super::*;
+
+    #[test]
+    fn renders_like_source() {
+        let compiled = std::env::temp_dir().join("das_ubershader_test_program.dasc");
+        compile(true, &compiled, &file("example3/test_program.das")).unwrap();
+
+        let mut cfg = Cfg::load(&file("example3/cfg.json")).unwrap();
+        cfg.program = Some(compiled.display().to_string());
+        let expected = image::open(file("example3/output.png")).unwrap();
+
+        assert_eq!(expected.into_rgba8(), render(&cfg).unwrap());
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let compiled = std::env::temp_dir().join("das_ubershader_old_program.dasc");
+        compile(false, &compiled, &file("example3/test_program.das")).unwrap();
+        let mut bytes = std::fs::read(&compiled).unwrap();
+        bytes[bytecode::MAGIC.len()] += 1;
+        std::fs::write(&compiled, bytes).unwrap();
+
+        let mut cfg = Cfg::load(&file("example3/cfg.json")).unwrap();
+        cfg.program = Some(compiled.display().to_string());
+        let e = expect_err(|e| matches!(e, RenderError::Bytecode { .. }), render(&cfg));
+        assert_eq!(8, e.exit_code());
+        assert!(e.to_string().contains("recompile the program"));
+    }
+
+    #[test]
+    fn write_failure() {
+        let compiled = std::env::temp_dir().join("das_ubershader_missing/program.dasc");
+        let e = expect_err(
+            |e| matches!(e, RenderError::Io { .. }),
+            compile(false, &compiled, &file("example3/test_program.das")),
+        );
+        assert_eq!(4, e.exit_code());
+    }
+}
+
+mod load {
+    use super::*;
+
+    #[test]
+    fn missing_file() {
+        let e = expect_err(
+            |e| matches!(e, RenderError::Io { .. }),
+            Cfg::load(&file("example/missing.json")),
+        );
+        assert_eq!(4, e.exit_code());
+    }
+
+    #[test]
+    fn named_inputs_in_written_order() {
+        let path = std::env::temp_dir().join("das_ubershader_named_inputs.json");
+        std::fs::write(
+            &path,
+            r#"{ "width": 1, "height": 1, "inputs": { "test": "test.jpg", "mummy": "mummy.jpg" } }"#,
+        )
+        .unwrap();
+        let cfg = Cfg::load(&path).unwrap();
+        let names: Vec<String> = cfg
+            .inputs
+            .to_vec()
+            .iter()
+            .map(|input| input.name())
+            .collect();
+
+        assert_eq!(vec!["test", "mummy"], names);
+    }
+}
+
+mod new {
+    use super::*;
+
+    #[test]
+    fn duplicate_input_names() {
+        let path = std::env::temp_dir().join("das_ubershader_duplicate_inputs.json");
+        std::fs::write(
+            &path,
+            r#"{ "width": 1, "height": 1, "inputs": { "a": "a.png", "a": "b.png" }, "program": "p.das" }"#,
+        )
+        .unwrap();
+        let cfg = Cfg::load(&path).unwrap();
+
+        let e = expect_err(
+            |e| matches!(e, RenderError::Config { .. }),
+            Renderer::new(&cfg, &cfg.uniforms()),
+        );
+        assert!(e.to_string().contains("more than one input named a"));
+    }
+
+    #[test]
+    fn duplicate_pass_names() {
+        let mut cfg = Cfg::load(&file("example4/cfg.json")).unwrap();
+        let name = cfg.passes[0].name.clone();
+        cfg.passes.last_mut().unwrap().name = name;
+
+        let e = expect_err(
+            |e| matches!(e, RenderError::Config { .. }),
+            Renderer::new(&cfg, &cfg.uniforms()),
+        );
+        assert!(e.to_string().contains("more than one pass is named small"));
+    }
+
+    #[test]
+    fn invalid_fps() {
+        for fps in [0.0, -30.0, f32::NAN, f32::INFINITY] {
+            let mut cfg = Cfg::load(&file("example3/cfg.json")).unwrap();
+            cfg.fps = Some(fps);
+
+            let e = expect_err(
+                |e| matches!(e, RenderError::Config { .. }),
+                Renderer::new(&cfg, &cfg.uniforms()),
+            );
+            assert!(e.to_string().contains("fps must be a positive number"));
+        }
+    }
+
+    #[test]
+    fn no_targets() {
+        let mut cfg = Cfg::load(&file("example4/cfg.json")).unwrap();
+        cfg.passes.last_mut().unwrap().targets = Some(0);
+
+        let e = expect_err(|e| matches!(e, RenderError::Config { .. }), render(&cfg));
+        assert!(e.to_string().contains("must render at least one target"));
+    }
+}
+
+mod render {
+    use super::*;
+
+    #[test]
+    fn example() {
+        let cfg = Cfg::load(&file("example3/cfg.json")).unwrap();
+        let expected = image::open(file("example3/output.png")).unwrap();
+
+        assert_eq!(expected.into_rgba8(), render(&cfg).unwrap());
+    }
+
+    #[test]
+    fn passes() {
+        let cfg = Cfg::load(&file("example4/cfg.json")).unwrap();
+        let expected = image::open(file("example4/output.png")).unwrap();
+
+        assert_eq!(expected.into_rgba8(), render(&cfg).unwrap());
+    }
+
+    #[test]
+    fn program_error() {
+        let mut cfg = Cfg::load(&file("example3/cfg.json")).unwrap();
+        cfg.program = Some("../example4/cfg.json".into());
+
+        let e = expect_err(|e| matches!(e, RenderError::Program { .. }), render(&cfg));
+        assert_eq!(7, e.exit_code());
+        assert!(e.render().contains("--> ../example4/cfg.json"));
+    }
+}
+
+mod render_frame {
+    use super::*;
+
+    #[test]
+    fn every_pass() {
+        let cfg = Cfg::load(&file("example4/cfg.json")).unwrap();
+        let passes = Renderer::new(&cfg, &cfg.uniforms())
+            .unwrap()
+            .render_frame(0)
+            .unwrap();
+
+        let dimensions: Vec<(u32, u32)> = passes
+            .iter()
+            .map(|targets| targets[0].dimensions())
+            .collect();
+        assert_eq!(vec![(32, 24), (320, 240)], dimensions);
+    }
+}
+
+mod save {
+    use super::*;
+
+    #[test]
+    fn numbered_sequence() {
+        let path = std::env::temp_dir().join("das_ubershader_sequence.png");
+        let mut cfg = Cfg::load(&file("example5/cfg.json")).unwrap();
+        cfg.frames = Some(3);
+        cfg.output = Some(Output::Path(path.display().to_string()));
+
+        Renderer::new(&cfg, &cfg.uniforms())
+            .unwrap()
+            .save()
+            .unwrap();
+
+        for frame in 0..3 {
+            let name = format!("das_ubershader_sequence_{:04}.png", frame);
+            assert!(path.with_file_name(name).exists());
+        }
+    }
+}
+
+mod trace {
+    use super::*;
+
+    #[test]
+    fn last_pass() {
+        let cfg = Cfg::load(&file("example4/cfg.json")).unwrap();
+        let lines = Renderer::new(&cfg, &cfg.uniforms())
+            .unwrap()
+            .trace((3, 2))
+            .unwrap();
+
+        assert_eq!(
+            1,
+            lines
+                .iter()
+                .filter(|line| line.starts_with("pass "))
+                .count()
+        );
+        assert_eq!("pass upsampled at 3,2", lines[0]);
+        assert!(lines.last().unwrap().starts_with("target 0 = "));
+    }
+}